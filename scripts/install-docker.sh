@@ -0,0 +1,34 @@
+#!/bin/sh
+# Install Docker Engine and the compose plugin from Docker's package repositories.
+# DOWNLOAD_URL points at https://download.docker.com or a mirror of it.
+set -e
+
+DOWNLOAD_URL="${DOWNLOAD_URL:-https://download.docker.com}"
+. /etc/os-release
+
+case "$ID" in
+ubuntu | debian | raspbian)
+    repo="$ID"
+    [ "$ID" = raspbian ] && repo=debian
+    apt-get update -qq
+    apt-get install -y -qq ca-certificates curl gnupg
+    install -m 0755 -d /etc/apt/keyrings
+    curl -fsSL "$DOWNLOAD_URL/linux/$repo/gpg" | gpg --dearmor --yes -o /etc/apt/keyrings/docker.gpg
+    chmod a+r /etc/apt/keyrings/docker.gpg
+    echo "deb [arch=$(dpkg --print-architecture) signed-by=/etc/apt/keyrings/docker.gpg] $DOWNLOAD_URL/linux/$repo $VERSION_CODENAME stable" \
+        >/etc/apt/sources.list.d/docker.list
+    apt-get update -qq
+    DEBIAN_FRONTEND=noninteractive apt-get install -y docker-ce docker-ce-cli containerd.io docker-compose-plugin
+    ;;
+fedora | centos | rhel | rocky | almalinux)
+    repo="$ID"
+    case "$ID" in rocky | almalinux) repo=centos ;; esac
+    curl -fsSL "$DOWNLOAD_URL/linux/$repo/docker-ce.repo" -o /etc/yum.repos.d/docker-ce.repo
+    sed -i "s#https://download.docker.com#$DOWNLOAD_URL#g" /etc/yum.repos.d/docker-ce.repo
+    dnf -y install docker-ce docker-ce-cli containerd.io docker-compose-plugin
+    ;;
+*)
+    echo "Docker's repositories don't support $ID, install Docker from the distribution instead" >&2
+    exit 1
+    ;;
+esac
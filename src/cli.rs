@@ -1,4 +1,7 @@
-use crate::commands;
+use crate::{
+    commands, print_error, print_warn,
+    utils::{confirm::Answers, oplog},
+};
 use clap::Parser;
 
 #[derive(Parser, Debug)]
@@ -6,6 +9,9 @@ use clap::Parser;
 pub struct Cli {
     #[clap(subcommand)]
     pub subcommand: SubCommand,
+
+    #[clap(flatten)]
+    pub answers: Answers,
 }
 
 #[derive(Parser, Debug)]
@@ -18,15 +24,74 @@ pub enum SubCommand {
 
     #[clap(name = "update")]
     Update(commands::update::Args),
+
+    #[clap(name = "bundle")]
+    Bundle(commands::bundle::Args),
+
+    #[clap(name = "doctor")]
+    Doctor(commands::doctor::Args),
+
+    #[clap(name = "verify")]
+    Verify(commands::verify::Args),
+
+    #[clap(name = "log")]
+    Log(commands::log::Args),
+
+    #[clap(name = "ip")]
+    Ip(commands::ip::Args),
 }
 
 pub async fn run() -> anyhow::Result<(), anyhow::Error> {
     let cmd = Cli::parse();
+    if let Err(e) = cmd.answers.install() {
+        print_error!("{:#}", e);
+    }
 
-    let _ = match cmd.subcommand {
+    let operation = match cmd.subcommand {
+        SubCommand::Install(_) => Some("install"),
+        SubCommand::Uninstall(_) => Some("uninstall"),
+        SubCommand::Update(_) => Some("update"),
+        _ => None,
+    };
+    let id = operation.and_then(|operation| match oplog::start(operation) {
+        Ok(id) => Some(id),
+        Err(e) => {
+            print_warn!("Not keeping a log of this {}: {:#}", operation, e);
+            None
+        }
+    });
+
+    let result = match cmd.subcommand {
         SubCommand::Install(cmd) => commands::install::run(cmd).await,
         SubCommand::Uninstall(cmd) => commands::uninstall::run(cmd).await,
         SubCommand::Update(cmd) => commands::update::run(cmd).await,
+        SubCommand::Bundle(cmd) => commands::bundle::run(cmd).await,
+        SubCommand::Doctor(cmd) => commands::doctor::run(cmd).await,
+        SubCommand::Verify(cmd) => commands::verify::run(cmd).await,
+        SubCommand::Log(cmd) => commands::log::run(cmd).await,
+        SubCommand::Ip(cmd) => commands::ip::run(cmd).await,
     };
+    if let Err(e) = result {
+        match id {
+            Some(id) => print_error!(
+                "{:#}\nSee `yacc log show {}` for everything that ran.",
+                e,
+                id
+            ),
+            None => print_error!("{:#}", e),
+        }
+    }
+    oplog::finish(true);
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cli() {
+        use clap::CommandFactory;
+        Cli::command().debug_assert();
+    }
+}
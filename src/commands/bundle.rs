@@ -0,0 +1,184 @@
+use crate::{
+    commands::install::{self, ReleaseArgs},
+    consts::CASA_PACKAGES,
+    print_info, print_ok, print_output,
+    utils::{
+        arch::Arch,
+        bundle::{self, Entry, Manifest},
+        rclone,
+        release::{self, Package},
+        signature,
+    },
+};
+use anyhow::Context;
+use console::style;
+use flate2::{write::GzEncoder, Compression};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Create an offline installation bundle
+#[derive(clap::Parser, Debug, Default)]
+pub struct Args {
+    /// Architecture to bundle packages for, defaults to this machine's
+    #[clap(long = "arch", value_enum)]
+    archs: Vec<Arch>,
+
+    /// Bundle file to write
+    #[clap(short, long, default_value = "casaos-bundle.tar.gz")]
+    output: PathBuf,
+
+    /// Docker image to include in the bundle, saved with `docker save`
+    #[clap(long = "image", value_name = "IMAGE")]
+    images: Vec<String>,
+
+    #[clap(flatten)]
+    release: ReleaseArgs,
+}
+
+pub async fn run(cmd: Args) -> anyhow::Result<(), anyhow::Error> {
+    let archs = if cmd.archs.is_empty() {
+        vec![install::check_arch(None)?]
+    } else {
+        cmd.archs.clone()
+    };
+
+    let work_dir = tempfile::tempdir()?;
+    let bundle_dir = work_dir.path().join("casaos-bundle");
+    let packages_dir = bundle_dir.join(bundle::PACKAGES_DIR);
+    std::fs::create_dir_all(&packages_dir)?;
+
+    let mirrors = install::select_mirrors(&cmd.release.mirror).await?;
+    let client = release::http_client()?;
+    let selection = cmd.release.selection();
+
+    print_info!("Resolving CasaOS versions...");
+    let releases = release::fetch_releases(
+        &client,
+        &mirrors,
+        &CASA_PACKAGES,
+        selection.index.as_deref(),
+    )
+    .await?;
+
+    // Architecture independent packages are shared by all architectures.
+    let mut packages: Vec<(String, Package)> = vec![];
+    for arch in archs.iter() {
+        for p in release::resolve(
+            &releases,
+            &CASA_PACKAGES,
+            &mirrors,
+            arch.as_str(),
+            &selection,
+        )? {
+            if packages.iter().any(|(_, q)| q.file_name == p.file_name) {
+                continue;
+            }
+            let per_arch = release::asset_stem(&p.name).is_some_and(|(_, per_arch)| per_arch);
+            let arch = if per_arch { arch.as_str() } else { "all" };
+            print_info!("{} {} ({})", p.name, style(&p.version).bold(), arch);
+            packages.push((arch.to_string(), p));
+        }
+    }
+
+    let mut list = packages.iter().map(|(_, p)| p.clone()).collect::<Vec<_>>();
+    print_info!("Downloading CasaOS...");
+    install::download_packages(
+        &client,
+        &mut list,
+        &packages_dir,
+        &cmd.release.download.options(),
+    )
+    .await?;
+    let digests =
+        install::verify_checksums(&client, &list, &packages_dir, cmd.release.skip_verify).await?;
+
+    for p in list.iter() {
+        if let Some(sig) = signature::fetch_signature(&client, p).await? {
+            std::fs::write(packages_dir.join(format!("{}.minisig", p.file_name)), sig)?;
+        }
+    }
+
+    // rclone is installed along with CasaOS and published with its own digests.
+    let mut rclone_list = archs
+        .iter()
+        .map(|arch| rclone::package(&mirrors, arch.as_str(), rclone::VERSION))
+        .collect::<Vec<_>>();
+    print_info!("Downloading rclone...");
+    install::download_packages(
+        &client,
+        &mut rclone_list,
+        &packages_dir,
+        &cmd.release.download.options(),
+    )
+    .await?;
+    let mut rclone_entries = vec![];
+    for (arch, p) in archs.iter().zip(rclone_list) {
+        let sha256 =
+            install::verify_rclone(&client, &p, &packages_dir, cmd.release.skip_verify).await?;
+        rclone_entries.push(Entry {
+            name: bundle::RCLONE.to_string(),
+            version: p.version,
+            arch: arch.to_string(),
+            file_name: p.file_name,
+            sha256: Some(sha256),
+        });
+    }
+
+    if !cmd.images.is_empty() {
+        save_images(&cmd.images, &bundle_dir.join(bundle::IMAGES_DIR))?;
+    }
+
+    let manifest = Manifest {
+        version: 1,
+        packages: packages
+            .into_iter()
+            .zip(digests)
+            .map(|((arch, p), sha256)| Entry {
+                name: p.name,
+                version: p.version,
+                arch,
+                file_name: p.file_name,
+                sha256: Some(sha256),
+            })
+            .chain(rclone_entries)
+            .collect(),
+    };
+    std::fs::write(
+        bundle_dir.join(bundle::MANIFEST_FILE),
+        serde_json::to_string_pretty(&manifest)?,
+    )?;
+
+    print_info!("Packing {}...", style(cmd.output.display()).bold());
+    let file = File::create(&cmd.output)
+        .with_context(|| format!("Failed to create {}", cmd.output.display()))?;
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    archive.append_dir_all("casaos-bundle", &bundle_dir)?;
+    archive.into_inner()?.finish()?;
+
+    print_output!("Bundle written to {}", cmd.output.display());
+    Ok(())
+}
+
+/// Save docker images into `dir`, one tarball per image.
+fn save_images(images: &[String], dir: &Path) -> anyhow::Result<(), anyhow::Error> {
+    std::fs::create_dir_all(dir)?;
+    for image in images {
+        print_info!("Saving image {}...", style(image).bold());
+        let file = dir.join(format!("{}.tar", image.replace(['/', ':'], "_")));
+        let status = Command::new("docker")
+            .arg("save")
+            .arg("-o")
+            .arg(&file)
+            .arg(image)
+            .status()
+            .context("Failed to run docker")?;
+        if !status.success() {
+            anyhow::bail!("Failed to save image {}", image);
+        }
+        print_ok!("{} Saved", image);
+    }
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use crate::{
+    commands::install,
+    consts::DATA_DIR,
+    print_output,
+    utils::{
+        output::{self, Format},
+        preflight::{self, Options},
+    },
+};
+use std::path::PathBuf;
+
+/// Check whether this machine is ready for CasaOS.
+/// Exits with 0 when every check passed, 1 with warnings and 2 with failures
+#[derive(clap::Parser, Debug, Default)]
+pub struct Args {
+    /// Minimum Docker Engine version [default: 20.10.0]
+    #[clap(long, value_name = "VERSION", env = "YACC_DOCKER_MIN_VERSION")]
+    docker_min_version: Option<String>,
+
+    /// Format of the report
+    #[clap(long, value_enum, default_value_t)]
+    output: Format,
+}
+
+pub async fn run(cmd: Args) -> anyhow::Result<(), anyhow::Error> {
+    if cmd.output == Format::Json {
+        output::status_to_stderr();
+    }
+
+    let options = Options {
+        root: PathBuf::from("/"),
+        data_dir: PathBuf::from(DATA_DIR),
+        docker_min_version: install::docker_min_version(cmd.docker_min_version.as_deref())?,
+        ports: preflight::DEFAULT_PORTS.to_vec(),
+        dns_host: preflight::DNS_PROBE_HOST.to_string(),
+    };
+    let report = preflight::run(&preflight::checks(&options));
+    match cmd.output {
+        Format::Json => print_output!("{}", serde_json::to_string_pretty(&report)?),
+        Format::Text => report.print(),
+    }
+
+    if report.exit_code() != 0 {
+        std::process::exit(report.exit_code());
+    }
+    Ok(())
+}
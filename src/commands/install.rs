@@ -1,37 +1,415 @@
 use crate::{
-    consts::CASA_SERVICES,
+    consts::{
+        CASA_PACKAGES, CASA_SERVICES, DATA_DIR, DEPENDENCIES, DEPENDENCIES_RECORD,
+        DOCKER_MIN_VERSION, TRUSTED_KEYS,
+    },
     print_error, print_info, print_ok, print_output, print_warn,
     utils::{
-        confirm::{confirm_default_no, confirm_default_yes},
-        file::replace_string_in_file,
+        arch::{self, Arch},
+        bundle, checksum,
+        config::Config,
+        confirm::{confirm_default_no, confirm_default_yes, Answers},
+        docker, download, gateway,
+        manifest::{self, Manifest},
+        migration, mirror, network, oplog,
+        output::{self, Format},
+        package::{self, Backend, Manager},
+        plan::{self, Plan},
+        preflight,
+        profile::{Channel, Profile},
+        rclone, readiness,
+        release::{self, Package, Selection},
+        script, signature,
         systemd::{self},
+        transaction::Transaction,
     },
 };
+use anyhow::Context;
 use console::style;
 use flate2::read::GzDecoder;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use ini::Ini;
-use reqwest::{
-    header::{CONTENT_LENGTH, RANGE},
-    Client,
-};
 use std::{
-    fs::File, io::Write, ops::Div, os::unix::prelude::PermissionsExt, path::Path, process::Command,
+    collections::HashMap,
+    fs::{File, Permissions},
+    io::Write,
+    os::unix::prelude::PermissionsExt,
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
 };
 use sys_info::linux_os_release;
 use tar::Archive;
-use tokio::{fs::OpenOptions, io::AsyncWriteExt};
 use walkdir::WalkDir;
 
 /// Install CasaOS
 #[derive(clap::Parser, Debug, Default)]
-pub struct Args {}
+pub struct Args {
+    #[clap(flatten)]
+    release: ReleaseArgs,
+
+    /// Also trust this minisign public key, as base64 or a path to a `.pub` file
+    #[clap(long = "trusted-key", value_name = "KEY")]
+    trusted_keys: Vec<String>,
+
+    /// Run the migration and setup scripts of packages without a valid signature
+    #[clap(long, default_value = "false")]
+    allow_unsigned: bool,
+
+    /// Refuse packages without a valid signature even when no signing key is
+    /// shipped with yacc or given with --trusted-key
+    #[clap(long, default_value = "false", conflicts_with = "allow_unsigned")]
+    require_signatures: bool,
+
+    /// Install offline from a bundle directory or .tar.gz instead of downloading
+    #[clap(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["version", "components", "alpha", "release_index"]
+    )]
+    from_bundle: Option<PathBuf>,
+
+    /// Print what would be installed, stopped, run and overwritten without changing anything
+    #[clap(long, default_value = "false")]
+    dry_run: bool,
+
+    #[clap(flatten)]
+    docker: DockerArgs,
+
+    /// Install into the system image mounted at this path instead of the running system.
+    /// Host checks are skipped and services are enabled offline with `systemctl --root`
+    #[clap(long, value_name = "PATH")]
+    root: Option<PathBuf>,
+
+    /// Architecture of the packages to install, defaults to this machine's.
+    /// Another one can only be installed with --root or planned with --dry-run
+    #[clap(long, value_enum)]
+    arch: Option<Arch>,
+
+    /// Read mirrors, versions, hooks and prompt answers from a TOML profile.
+    /// Flags given on the command line take precedence
+    #[clap(long, value_name = "PATH")]
+    profile: Option<PathBuf>,
+
+    /// Print each migration and setup script before running it
+    #[clap(long, default_value = "false")]
+    show_scripts: bool,
+
+    /// Only run package scripts with this SHA-256, may be given multiple times
+    #[clap(long = "allow-script", value_name = "SHA256")]
+    allowed_scripts: Vec<String>,
+
+    /// Refuse to install when a package script has this SHA-256, may be given multiple times
+    #[clap(long = "deny-script", value_name = "SHA256")]
+    denied_scripts: Vec<String>,
+
+    /// Port the CasaOS gateway listens on, written to gateway.ini.
+    /// An installed CasaOS keeps its port by default
+    #[clap(long, value_parser = clap::value_parser!(u16).range(1..))]
+    port: Option<u16>,
+
+    /// Install even when something else listens on the gateway port
+    #[clap(long, default_value = "false")]
+    force_port: bool,
+
+    /// Seconds a migration or setup script may run before it is killed
+    #[clap(long, value_name = "SECS", default_value_t = script::DEFAULT_TIMEOUT.as_secs())]
+    script_timeout: u64,
+
+    /// Seconds each service has to become ready after it is started
+    #[clap(long, value_name = "SECS", default_value_t = readiness::DEFAULT_TIMEOUT.as_secs())]
+    ready_timeout: u64,
+
+    /// Format of the dry run plan
+    #[clap(long, value_enum, default_value_t, requires = "dry_run")]
+    output: Format,
+}
 
-pub async fn run(_cmd: Args) -> anyhow::Result<(), anyhow::Error> {
-    // clear screen
-    console::Term::stdout().clear_screen()?;
-    print_output!(
-        r#"
+/// How Docker is provisioned when it is missing or too old.
+#[derive(clap::Args, Debug, Default)]
+pub struct DockerArgs {
+    /// Minimum Docker Engine version, older ones are upgraded [default: 20.10.0]
+    #[clap(long, value_name = "VERSION", env = "YACC_DOCKER_MIN_VERSION")]
+    docker_min_version: Option<String>,
+
+    /// Where to install Docker from, defaults to Docker's repositories on the distributions they serve
+    #[clap(long, value_enum)]
+    docker_from: Option<docker::Source>,
+
+    /// Mirror of download.docker.com used to install Docker from its repositories
+    #[clap(long, value_name = "URL", env = "YACC_DOCKER_MIRROR")]
+    docker_mirror: Option<String>,
+}
+
+/// Which package versions to fetch and how to verify them, shared with `bundle`.
+#[derive(clap::Args, Debug, Default)]
+pub struct ReleaseArgs {
+    #[clap(flatten)]
+    pub mirror: MirrorArgs,
+
+    #[clap(flatten)]
+    pub download: DownloadArgs,
+
+    /// Install the newest CasaOS not newer than this version, other components are not capped
+    #[clap(long)]
+    version: Option<String>,
+
+    /// Pin a component to a version, e.g. CasaOS-Gateway=0.4.2
+    #[clap(long = "component", value_name = "NAME=VERSION", value_parser = parse_component)]
+    components: Vec<(String, String)>,
+
+    /// Also consider pre-releases
+    #[clap(short, long, default_value = "false")]
+    alpha: bool,
+
+    /// Read all component releases from a single JSON index instead
+    #[clap(long, value_name = "URL")]
+    release_index: Option<String>,
+
+    /// Do not verify package checksums, for mirrors that don't publish them
+    #[clap(long, default_value = "false")]
+    pub skip_verify: bool,
+}
+
+/// Where packages are downloaded from.
+#[derive(clap::Args, Debug, Default)]
+pub struct MirrorArgs {
+    /// Download mirror, may be given multiple times, the fastest answering one is used first
+    #[clap(
+        long = "mirror",
+        value_name = "URL",
+        env = "YACC_MIRRORS",
+        value_delimiter = ','
+    )]
+    mirrors: Vec<String>,
+
+    /// Prefer the mirror for the region reported by ipapi.co
+    #[clap(long, env = "YACC_DETECT_REGION")]
+    detect_region: bool,
+}
+
+/// How packages are downloaded.
+#[derive(clap::Args, Debug)]
+pub struct DownloadArgs {
+    /// Number of packages downloaded at the same time
+    #[clap(long, default_value = "4", value_parser = clap::value_parser!(u16).range(1..))]
+    parallel: u16,
+
+    /// Attempts per mirror before moving on to the next one
+    #[clap(long, default_value = "3")]
+    retries: u32,
+
+    /// Limit the total download speed, e.g. 500K or 2M bytes per second
+    #[clap(long, value_name = "RATE", value_parser = download::parse_rate)]
+    limit_rate: Option<u64>,
+}
+
+impl Default for DownloadArgs {
+    fn default() -> Self {
+        let options = download::Options::default();
+        DownloadArgs {
+            parallel: options.parallel as u16,
+            retries: options.retries,
+            limit_rate: options.limit_rate,
+        }
+    }
+}
+
+impl DownloadArgs {
+    pub fn options(&self) -> download::Options {
+        download::Options {
+            parallel: self.parallel as usize,
+            retries: self.retries,
+            limit_rate: self.limit_rate,
+            ..Default::default()
+        }
+    }
+}
+
+impl ReleaseArgs {
+    pub fn selection(&self) -> Selection {
+        Selection {
+            alpha: self.alpha,
+            version: self.version.clone(),
+            pins: self.components.iter().cloned().collect::<HashMap<_, _>>(),
+            index: self.release_index.clone(),
+        }
+    }
+}
+
+impl Args {
+    pub fn root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+
+    pub fn is_bundle(&self) -> bool {
+        self.from_bundle.is_some()
+    }
+}
+
+/// The packages `install` would download with these arguments, without downloading them.
+pub async fn latest_packages(cmd: &mut Args) -> anyhow::Result<Vec<Package>, anyhow::Error> {
+    if let Some(path) = &cmd.profile {
+        let profile = Profile::load(path)?;
+        apply_profile(cmd, &profile);
+    }
+    let arch = check_arch(cmd.arch)?;
+    let mirrors = select_mirrors(&cmd.release.mirror).await?;
+    resolve_packages(&mirrors, arch.as_str(), &cmd.release).await
+}
+
+/// Fill in what the command line left unset from the profile.
+fn apply_profile(cmd: &mut Args, profile: &Profile) {
+    let release = &mut cmd.release;
+    if release.mirror.mirrors.is_empty() {
+        release.mirror.mirrors = profile.mirrors.clone();
+    }
+    if profile.channel == Some(Channel::Alpha) {
+        release.alpha = true;
+    }
+    if release.version.is_none() {
+        release.version = profile.version.clone();
+    }
+    for (name, version) in profile.components.iter() {
+        if !release.components.iter().any(|(n, _)| n == name) {
+            release.components.push((name.clone(), version.clone()));
+        }
+    }
+    if cmd.port.is_none() {
+        cmd.port = profile.gateway_port;
+    }
+    Answers::extend(&profile.answers);
+}
+
+/// Print the settings resolved from the profile and the command line.
+fn print_summary(cmd: &Args, profile: &Profile) {
+    let release = &cmd.release;
+    let or = |value: String, default: &str| match value.is_empty() {
+        true => default.to_string(),
+        false => value,
+    };
+    let on_off = |on: bool| if on { "yes" } else { "no" };
+    print_info!("{}", style("Install settings:").bold());
+    print_info!(
+        "  Mirrors        : {}",
+        or(release.mirror.mirrors.join(", "), "default")
+    );
+    print_info!(
+        "  Channel        : {}",
+        if release.alpha { "alpha" } else { "stable" }
+    );
+    print_info!(
+        "  Version        : {}",
+        release.version.as_deref().unwrap_or("latest")
+    );
+    print_info!(
+        "  Components     : {}",
+        or(
+            release
+                .components
+                .iter()
+                .map(|(n, v)| format!("{}={}", n, v))
+                .collect::<Vec<_>>()
+                .join(", "),
+            "-"
+        )
+    );
+    print_info!(
+        "  Gateway port   : {}",
+        profile
+            .gateway_port
+            .map(|p| p.to_string())
+            .unwrap_or_else(|| "from the package".to_string())
+    );
+    print_info!(
+        "  Data directory : {}",
+        profile
+            .data_dir
+            .as_deref()
+            .unwrap_or(Path::new(DATA_DIR))
+            .display()
+    );
+    print_info!("  Install Docker : {}", on_off(profile.install_docker()));
+    print_info!("  Install rclone : {}", on_off(profile.install_rclone()));
+    print_info!(
+        "  Dependencies   : {}",
+        DEPENDENCIES
+            .iter()
+            .map(|d| d.to_string())
+            .chain(profile.dependencies.iter().cloned())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    print_info!(
+        "  Hooks          : {} pre-install, {} post-install",
+        profile.hooks.pre_install.len(),
+        profile.hooks.post_install.len()
+    );
+    let mut answers = profile
+        .answers
+        .iter()
+        .map(|(id, a)| format!("{}={}", id, a))
+        .collect::<Vec<_>>();
+    answers.sort();
+    print_info!("  Answers        : {}", or(answers.join(", "), "-"));
+}
+
+/// Run profile hooks with `sh -c`, stopping at the first one that fails.
+/// `YACC_ROOT` tells them where CasaOS is installed.
+fn run_hooks(
+    kind: &str,
+    hooks: &[String],
+    root: Option<&Path>,
+) -> anyhow::Result<(), anyhow::Error> {
+    let root = root.unwrap_or(Path::new("/"));
+    for hook in hooks {
+        print_info!("Running {} hook {}...", kind, style(hook).bold());
+        let status = oplog::run(
+            Command::new("sh")
+                .arg("-c")
+                .arg(hook)
+                .env("YACC_ROOT", root),
+        )?;
+        if !status.success() {
+            anyhow::bail!("The {} hook `{}` failed, {}", kind, hook, status);
+        }
+    }
+    Ok(())
+}
+
+fn parse_component(s: &str) -> anyhow::Result<(String, String), anyhow::Error> {
+    let (name, version) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("expected NAME=VERSION, got `{}`", s))?;
+    if !CASA_PACKAGES.contains(&name) {
+        anyhow::bail!(
+            "unknown component `{}`, expected one of: {}",
+            name,
+            CASA_PACKAGES.join(", ")
+        );
+    }
+    Ok((name.to_string(), version.to_string()))
+}
+
+pub async fn run(mut cmd: Args) -> anyhow::Result<(), anyhow::Error> {
+    if cmd.output == Format::Json {
+        output::status_to_stderr();
+    }
+    let profile = match &cmd.profile {
+        Some(path) => match Profile::load(path) {
+            Ok(profile) => profile,
+            Err(e) => {
+                print_error!("{:#}", e);
+            }
+        },
+        None => Profile::default(),
+    };
+    apply_profile(&mut cmd, &profile);
+
+    if cmd.output != Format::Json {
+        // clear screen
+        console::Term::stdout().clear_screen()?;
+        print_output!(
+            r#"
    _____                 ____   _____
   / ____|               / __ \ / ____|
  | |     __ _ ___  __ _| |  | | (___
@@ -41,54 +419,244 @@ pub async fn run(_cmd: Args) -> anyhow::Result<(), anyhow::Error> {
 
    --- Made by IceWhale with YOU ---
     "#
-    );
+        );
+    }
+    if cmd.profile.is_some() {
+        print_summary(&cmd, &profile);
+    }
 
-    // Step 0 : Get Download Url Domain
-    let download_domain = get_download_domain().unwrap();
-    let arch = check_arch().unwrap();
-    let distro = check_distro().unwrap();
+    let root = match &cmd.root {
+        Some(root) => Some(
+            root.canonicalize()
+                .with_context(|| format!("Invalid root {}", root.display()))?,
+        ),
+        None => None,
+    };
 
-    print_info!(
-        "Your hardware architecture is: {}",
-        style(arch.clone()).bold()
-    );
+    let cpu = arch::detect();
+    print_info!("Your hardware architecture is: {}", style(&cpu.name).bold());
+    let arch = match check_arch(cmd.arch) {
+        Ok(arch) => arch,
+        Err(e) => {
+            print_error!("{:#}", e);
+        }
+    };
+    if cpu.arch != Some(arch) && root.is_none() && !cmd.dry_run {
+        print_error!(
+            "{} packages don't run on {}, use --root to install them into an image",
+            arch,
+            cpu.name
+        );
+    }
+    let arch = arch.as_str();
 
     if !cfg!(target_os = "linux") {
         print_error!("This is only for Linux.");
     }
-    print_info!("Your System is: {}", style(distro.clone()).bold());
 
-    print_info!("Your Linux Distribution is: {}", style(distro).bold());
+    if let Some(root) = &root {
+        print_info!(
+            "Installing into {}, skipping host checks.",
+            style(root.display()).bold()
+        );
+    } else {
+        oplog::step("pre-flight checks");
+        print_info!("Running pre-flight checks...");
+        if let Err(e) = preflight_gate(&cmd, &profile) {
+            print_error!("{:#}", e);
+        }
+        // Checked before anything is installed or enabled, the packages only
+        // matter when neither `--port` nor an earlier install sets it.
+        let installed_ini = Path::new("/").join(gateway::GATEWAY_INI);
+        let port = cmd
+            .port
+            .or_else(|| gateway::port(&installed_ini))
+            .unwrap_or(gateway::DEFAULT_PORT);
+        gate_port(port, &installed_ini, &cmd);
+    }
 
-    match check_memory() {
-        Ok(_) => print_info!("Memory capacity check passed.",),
-        Err(e) => {
-            print_error!("{}", e);
+    if root.is_none() && !cmd.dry_run {
+        oplog::step("dependencies");
+        print_info!("Updating dependencies...");
+        if let Err(e) = update_denpendencies(&profile.dependencies) {
+            print_error!("{:#}", e);
         }
     }
-    match check_disk() {
-        Ok(_) => print_info!("Disk capacity check passed.",),
-        Err(e) => {
-            print_error!("{}", e);
+
+    if root.is_none() && !cmd.dry_run && !profile.install_docker() {
+        print_warn!("Skipping Docker, the profile turns it off.");
+    } else if root.is_none() && !cmd.dry_run {
+        oplog::step("docker");
+        print_info!("Checking Docker...");
+        if let Err(e) = check_docker(&cmd.docker, cmd.release.mirror.detect_region).await {
+            print_error!("{:#}", e);
         }
     }
 
-    print_info!("Updating dependencies...");
-    // update_denpendencies().unwrap();
+    oplog::step("packages");
+    let work_dir = tempfile::tempdir()?;
+    let tmp_dir = work_dir.path().join("casaos");
+    std::fs::create_dir_all(&tmp_dir)?;
 
-    print_info!("Checking Docker...");
-    // check_docker().unwrap();
+    // rclone is only fetched when it is missing, an existing binary is kept.
+    let need_rclone = !cmd.dry_run
+        && profile.install_rclone()
+        && !rclone::installed(root.as_deref().unwrap_or(Path::new("/")));
+    let mut rclone_binary = None;
+
+    let mut bundle_dir = None;
+    let (source, packages) = match &cmd.from_bundle {
+        Some(bundle) => {
+            print_info!("Reading bundle {}...", style(bundle.display()).bold());
+            let packages = match open_bundle(bundle, arch, &tmp_dir, &cmd) {
+                Ok((dir, packages)) => {
+                    bundle_dir = Some(dir);
+                    packages
+                }
+                Err(e) => {
+                    print_error!("{:#}", e);
+                }
+            };
+            if need_rclone {
+                let dir = bundle_dir.as_deref().unwrap();
+                match bundle_rclone(dir, arch, &tmp_dir, cmd.release.skip_verify) {
+                    Ok(Some(binary)) => rclone_binary = Some(binary),
+                    Ok(None) => print_warn!(
+                        "The bundle has no rclone for {}, {} will not be started.",
+                        arch,
+                        rclone::UNIT
+                    ),
+                    Err(e) => {
+                        print_error!("{:#}", e);
+                    }
+                }
+            }
+            (bundle.display().to_string(), packages)
+        }
+        None => {
+            // Step 0 : Select Download Mirrors
+            let mirrors = match select_mirrors(&cmd.release.mirror).await {
+                Ok(mirrors) => mirrors,
+                Err(e) => {
+                    print_error!("{:#}", e);
+                }
+            };
 
-    print_info!("Downloading CasaOS...");
-    match download_and_install_casaos(download_domain, arch).await {
-        Ok(_) => {}
-        Err(e) => {
-            print_error!("{}", e);
+            print_info!("Resolving CasaOS versions...");
+            let mut packages = match resolve_packages(&mirrors, arch, &cmd.release).await {
+                Ok(packages) => packages,
+                Err(e) => {
+                    print_error!("{:#}", e);
+                }
+            };
+            for package in packages.iter() {
+                print_info!("{} {}", package.name, style(&package.version).bold());
+            }
+
+            print_info!("Downloading CasaOS...");
+            if let Err(e) = download_casaos(&mut packages, &tmp_dir, &cmd).await {
+                print_error!("{:#}", e);
+            }
+
+            if need_rclone {
+                print_info!("Downloading rclone...");
+                match download_rclone(&mirrors, arch, &tmp_dir, &cmd.release).await {
+                    Ok(binary) => rclone_binary = Some(binary),
+                    Err(e) => {
+                        print_error!("{:#}", e);
+                    }
+                }
+            }
+            (mirrors.first().cloned().unwrap_or_default(), packages)
+        }
+    };
+
+    oplog::step("extract");
+    let owners = extract_packages(&packages, &tmp_dir)?;
+    if root.is_none() {
+        script_policy(&cmd)?.check(&tmp_dir.join("build"), &package_scripts(&tmp_dir))?;
+    }
+    let packaged_ini = tmp_dir
+        .join("build")
+        .join(plan::SYSROOT_DIR)
+        .join(gateway::GATEWAY_INI);
+    let installed_ini = root
+        .as_deref()
+        .unwrap_or(Path::new("/"))
+        .join(gateway::GATEWAY_INI);
+    // Keep the port of an earlier install unless another one is asked for.
+    let port = cmd.port.or_else(|| gateway::port(&installed_ini));
+    if let Some(port) = port {
+        gateway::set_port(&packaged_ini, port)?;
+    }
+    if root.is_none() && port.is_none() {
+        if let Some(packaged) = gateway::port(&packaged_ini) {
+            if packaged != gateway::DEFAULT_PORT {
+                gate_port(packaged, &installed_ini, &cmd);
+            }
         }
     }
 
+    if cmd.dry_run {
+        let stop_services = match root {
+            Some(_) => vec![],
+            None => CASA_SERVICES
+                .iter()
+                .filter(|s| matches!(systemd::exists(s), Ok(true)))
+                .map(|s| s.to_string())
+                .collect(),
+        };
+        let enable_services = CASA_SERVICES.iter().map(|s| s.to_string()).collect();
+        let mut plan = Plan::build(
+            source,
+            &packages,
+            &tmp_dir,
+            root.as_deref().unwrap_or(Path::new("/")),
+            stop_services,
+            enable_services,
+        )?;
+        if root.is_some() {
+            // Scripts are not run when installing into another root.
+            plan.migration_scripts.clear();
+            plan.setup_scripts.clear();
+        }
+        match cmd.output {
+            Format::Json => print_output!("{}", serde_json::to_string_pretty(&plan)?),
+            Format::Text => plan.print(),
+        }
+        return Ok(());
+    }
+
+    oplog::step("install");
+    run_hooks("pre-install", &profile.hooks.pre_install, root.as_deref())?;
+    let scripts = scripts(&cmd, &packages, root.as_deref());
+    install_casaos(
+        &tmp_dir,
+        rclone_binary.as_deref(),
+        profile.data_dir.as_deref(),
+        &owners,
+        &scripts,
+        &work_dir.path().join("backup"),
+        root.as_deref(),
+    )?;
+    if let Some(root) = &root {
+        if bundle_dir.is_some_and(|dir| !bundle::images(&dir).is_empty()) {
+            print_warn!("Docker images of the bundle are not loaded into another root.");
+        }
+        run_hooks("post-install", &profile.hooks.post_install, Some(root))?;
+        print_ok!("CasaOS installed into {}", root.display());
+        return Ok(());
+    }
+    if let Some(dir) = bundle_dir {
+        load_images(&dir);
+    }
+    drop(work_dir);
+
     // Step 10: Check Service Status
-    check_service_status().unwrap();
+    oplog::step("service status");
+    check_service_status(Duration::from_secs(cmd.ready_timeout));
+
+    run_hooks("post-install", &profile.hooks.post_install, None)?;
 
     // Step 11: Clear Term and Show Welcome Banner
     welcome_banner().unwrap();
@@ -96,488 +664,1118 @@ pub async fn run(_cmd: Args) -> anyhow::Result<(), anyhow::Error> {
     Ok(())
 }
 
-fn get_region() -> anyhow::Result<String, anyhow::Error> {
-    let client = reqwest::blocking::Client::new();
-    let res = client.get("https://ipapi.co/json").send().unwrap();
+/// Get the download mirrors, ordered by latency.
+/// Mirrors come from `--mirror`, `YACC_MIRRORS` or the config file, in that order,
+/// and default to GitHub and Aliyun OSS. With region detection enabled the mirror
+/// serving the region is preferred, e.g. Aliyun OSS for China.
+pub async fn select_mirrors(args: &MirrorArgs) -> anyhow::Result<Vec<String>, anyhow::Error> {
+    let config = Config::load()?;
+    let configured = if !args.mirrors.is_empty() {
+        args.mirrors.clone()
+    } else if !config.mirrors.is_empty() {
+        config.mirrors
+    } else {
+        mirror::DEFAULT_MIRRORS
+            .iter()
+            .map(|m| m.to_string())
+            .collect()
+    };
+    let mut mirrors: Vec<String> = vec![];
+    for m in configured.iter().map(|m| mirror::normalize(m)) {
+        if !mirrors.contains(&m) {
+            mirrors.push(m);
+        }
+    }
 
-    let response = res.json::<serde_json::Value>().unwrap();
+    let client = release::http_client()?;
+    let mut ranked = mirror::rank(&client, &mirrors).await;
+
+    if args.detect_region || config.detect_region {
+        match mirror::detect_region(&client).await {
+            Some(country_code) => {
+                print_info!("Your region is: {}", style(&country_code).bold());
+                if let Some(preferred) = mirror::preferred_for_region(&country_code) {
+                    if let Some(i) = ranked.iter().position(|m| m.url == preferred) {
+                        let m = ranked.remove(i);
+                        ranked.insert(0, m);
+                    }
+                }
+            }
+            None => print_warn!("Failed to detect region, ordering mirrors by latency only."),
+        }
+    }
 
-    if let Some(country_code) = response["country_code"].as_str() {
-        if country_code == "CN" {
-            Ok("cn".to_string())
-        } else {
-            Ok("global".to_string())
+    for m in ranked.iter() {
+        match m.latency {
+            Some(latency) => print_info!("Mirror {} ({} ms)", m.url, latency.as_millis()),
+            None => print_warn!("Mirror {} is unreachable", m.url),
         }
-    } else {
-        print_warn!("Failed to get country code, use global as default.");
-        Ok("global".to_string())
     }
+    if ranked.iter().all(|m| m.latency.is_none()) {
+        print_warn!("No mirror answered, the download will likely fail.");
+    }
+    Ok(ranked.into_iter().map(|m| m.url).collect())
 }
 
-/// Get the download domain by region.
-/// For China, use Aliyun OSS.
-/// For other regions, use Github.
-fn get_download_domain() -> anyhow::Result<String, anyhow::Error> {
-    let region = get_region().unwrap();
-
-    if region == "cn" {
-        return Ok("https://casaos.oss-cn-shanghai.aliyuncs.com/IceWhaleTech/".to_string());
+/// The architecture to install, this machine's unless `arch` overrides it.
+pub fn check_arch(arch: Option<Arch>) -> anyhow::Result<Arch, anyhow::Error> {
+    if let Some(arch) = arch {
+        return Ok(arch);
     }
-    Ok("https://github.com/IceWhaleTech/".to_string())
+    let cpu = arch::detect();
+    cpu.arch.with_context(|| {
+        format!(
+            "Unsupported architecture {}, CasaOS runs on amd64, arm64 and ARMv7 or newer",
+            cpu.name
+        )
+    })
 }
 
-/// Check architecture, only amd64, arm64 and arm-7 are supported.
-fn check_arch() -> anyhow::Result<String, anyhow::Error> {
-    let env_arch = std::env::consts::ARCH;
-    let supported_archs = vec!["x86_64", "aarch64", "armv7h"];
-    let arch = match env_arch {
-        "x86_64" => "amd64",
-        "aarch64" => "arm64",
-        "arm" => "arm-7",
-        _ => env_arch,
-    };
-
-    if !supported_archs.contains(&env_arch) {
-        print_error!("Unsupported architecture: {}", arch);
+/// Stop the install when the gateway port is taken, only warn on a dry run.
+fn gate_port(port: u16, installed_ini: &Path, cmd: &Args) {
+    match check_port(port, installed_ini, cmd.force_port) {
+        Err(e) if cmd.dry_run => print_warn!("{:#}", e),
+        Err(e) => print_error!("{:#}", e),
+        Ok(_) => {}
     }
-
-    Ok(arch.to_string())
 }
 
-/// Check distro
-fn check_distro() -> anyhow::Result<String, anyhow::Error> {
-    Ok(linux_os_release().unwrap().id.unwrap())
+/// Refuse a gateway port something else listens on, suggesting a free one.
+/// The gateway of an installed CasaOS is stopped before upgrading, so its port counts as free.
+fn check_port(port: u16, installed_ini: &Path, force: bool) -> anyhow::Result<(), anyhow::Error> {
+    let listening = preflight::listening_ports(Path::new("/"));
+    if !listening.contains(&port) {
+        return Ok(());
+    }
+    let ours = installed_ini.exists()
+        && gateway::port(installed_ini).unwrap_or(gateway::DEFAULT_PORT) == port
+        && matches!(systemd::is_active(gateway::SERVICE), Ok(true));
+    if ours {
+        return Ok(());
+    }
+    if force {
+        print_warn!(
+            "Port {} is in use, installing anyway as --force-port was given.",
+            port
+        );
+        return Ok(());
+    }
+    match gateway::free_port(port, &listening) {
+        Some(free) => anyhow::bail!(
+            "Port {} is already in use, choose another one, e.g. --port {}, or pass --force-port",
+            port,
+            free
+        ),
+        None => anyhow::bail!("Port {} is already in use and no port is free", port),
+    }
 }
 
-/// Check memory
-fn check_memory() -> anyhow::Result<(), anyhow::Error> {
-    let memory = (sys_info::mem_info().unwrap().total as f64).div(1024.0);
-
-    if memory < 400.0 {
-        Err(anyhow::anyhow!("Requires atleast 400MB physical memory."))
-    } else {
-        Ok(())
+/// Run the pre-flight checks, refusing to install on failures and asking before
+/// going on with warnings. Docker is left to `check_docker`, which fixes it, and the
+/// gateway port to `check_port`, which tells an installed gateway apart.
+fn preflight_gate(cmd: &Args, profile: &Profile) -> anyhow::Result<(), anyhow::Error> {
+    let options = preflight::Options {
+        root: PathBuf::from("/"),
+        data_dir: profile
+            .data_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DATA_DIR)),
+        docker_min_version: docker_min_version(cmd.docker.docker_min_version.as_deref())?,
+        ports: vec![],
+        dns_host: preflight::DNS_PROBE_HOST.to_string(),
+    };
+    let checks = preflight::checks(&options)
+        .into_iter()
+        .filter(|c| c.id() != "docker" && c.id() != "ports")
+        // A bundle installs without network.
+        .filter(|c| !(c.id() == "dns" && cmd.from_bundle.is_some()))
+        .collect::<Vec<_>>();
+    let report = preflight::run(&checks);
+    report.print();
+
+    match report.status {
+        preflight::Status::Pass => Ok(()),
+        preflight::Status::Fail if cmd.dry_run => {
+            print_warn!("Pre-flight checks failed, the installation would stop here.");
+            Ok(())
+        }
+        preflight::Status::Fail => anyhow::bail!("Pre-flight checks failed, see `yacc doctor`"),
+        preflight::Status::Warn if cmd.dry_run => Ok(()),
+        preflight::Status::Warn => {
+            if confirm_default_no(
+                "preflight_continue",
+                "Some pre-flight checks warned, continue installation?",
+            )? {
+                Ok(())
+            } else {
+                anyhow::bail!("Installation cancelled.")
+            }
+        }
     }
 }
 
-/// Check disk
-fn check_disk() -> anyhow::Result<(), anyhow::Error> {
-    let disk = (sys_info::disk_info().unwrap().free as f64)
-        .div(1024.0)
-        .div(1024.0);
-
-    if disk < 5.0 {
-        let _ = match confirm_default_no(format!("Recommended fress disk space is greater than 5 GB, Current free disk space is {:.2}GB\nContinue installation?", disk).as_str()).unwrap() {
-            true => Ok(()),
-            false => Err(anyhow::anyhow!("Installation cancelled.")),
-        };
+/// Install the missing `DEPENDENCIES` and `extra` packages with the distribution's package manager
+/// and record which ones were installed.
+fn update_denpendencies(extra: &[String]) -> anyhow::Result<(), anyhow::Error> {
+    let dependencies = DEPENDENCIES
+        .iter()
+        .copied()
+        .chain(extra.iter().map(String::as_str))
+        .collect::<Vec<_>>();
+    let (id, manager) = detect_manager()
+        .with_context(|| format!("Please install {} manually", dependencies.join(", ")))?;
+
+    let outcome = package::ensure(&mut package::System::new(manager), &dependencies)?;
+    if !outcome.installed.is_empty() {
+        print_ok!("Installed {}", outcome.installed.join(", "));
+        package::record(Path::new(DEPENDENCIES_RECORD), manager, &outcome.installed)?;
+    }
+    if !outcome.unavailable.is_empty() {
+        print_warn!(
+            "Could not find these packages, some features may not work: {}",
+            outcome.unavailable.join(", ")
+        );
+    }
+    if !outcome.unsupported.is_empty() {
+        print_warn!(
+            "Not packaged for {}: {}",
+            id,
+            outcome.unsupported.join(", ")
+        );
     }
+    print_ok!("Dependencies are up to date.");
     Ok(())
 }
 
-// TODO
-fn update_denpendencies() -> anyhow::Result<(), anyhow::Error> {
-    Ok(())
+/// Minimum Docker Engine version from `--docker-min-version`, the config file or the default.
+pub fn docker_min_version(arg: Option<&str>) -> anyhow::Result<String, anyhow::Error> {
+    match arg {
+        Some(version) => Ok(version.to_string()),
+        None => Ok(Config::load()?
+            .docker_min_version
+            .unwrap_or_else(|| DOCKER_MIN_VERSION.to_string())),
+    }
 }
 
-// TODO
-fn check_docker() -> anyhow::Result<(), anyhow::Error> {
-    // check if docker is installed
-    let docker_version = Command::new("docker")
-        .arg("--version")
-        .arg("--format")
-        .output()?;
-
-    // if not installed, install docker
-    if docker_version.status.code().unwrap() != 0 {
-        let _ = match confirm_default_yes("Docker is not installed, install it now?").unwrap() {
-            true => {
-                print_output!("Installing docker...");
-                let region = get_region().unwrap();
-                let child = Command::new("curl")
-                    .arg("-fsSL")
-                    .arg("https://get.docker.com")
-                    .arg("-o")
-                    .arg("get-docker.sh")
-                    .spawn()
-                    .expect("failed to execute process");
-
-                let _ = child.wait_with_output().expect("failed to wait on child");
-                if region == "cn" {
-                    let _ = Command::new("bash")
-                        .arg("-s")
-                        .arg("docker")
-                        .arg("--mirror")
-                        .arg("Aliyun")
-                        .output()
-                        .expect("failed to execute process");
-                } else {
-                    let _ = Command::new("bash")
-                        .arg("get-docker.sh")
-                        .output()
-                        .expect("failed to execute process");
-                }
-                let _ = std::fs::remove_file("get-docker.sh");
-                print_output!("Docker installed.");
-                Ok(())
-            }
-            false => Err(anyhow::anyhow!("Installation cancelled.")),
-        };
+/// Make sure Docker Engine of at least the minimum version and the compose plugin
+/// are installed, installing or upgrading them if needed, and that the daemon is up.
+async fn check_docker(args: &DockerArgs, detect_region: bool) -> anyhow::Result<(), anyhow::Error> {
+    let config = Config::load()?;
+    let minimum = docker_min_version(args.docker_min_version.as_deref())?;
+
+    let found = docker::detect();
+    let outdated = match &found.engine {
+        Some(version) if docker::satisfies(version, &minimum) => {
+            print_ok!("Docker {} found", version);
+            false
+        }
+        Some(version) => {
+            print_warn!("Docker {} is older than the required {}", version, minimum);
+            true
+        }
+        None => {
+            print_warn!("Docker is not installed.");
+            true
+        }
+    };
+    match &found.compose {
+        Some(version) => print_ok!("Docker Compose {} found", version),
+        None => print_warn!("The Docker Compose plugin is not installed."),
     }
 
-    // if installed, check docker version
-    print_info!("Checking docker version...");
+    if outdated || found.compose.is_none() {
+        if !confirm_default_yes("install_docker", "Install Docker now?")? {
+            anyhow::bail!(
+                "Docker {} or newer with the compose plugin is required",
+                minimum
+            );
+        }
+        install_docker(args, &config, detect_region || config.detect_region).await?;
+
+        let found = docker::detect();
+        match found.engine {
+            Some(version) if docker::satisfies(&version, &minimum) => {
+                print_ok!("Docker {} installed", version)
+            }
+            Some(version) => anyhow::bail!(
+                "Docker {} was installed but {} or newer is required, try --docker-from script",
+                version,
+                minimum
+            ),
+            None => anyhow::bail!("Docker is still not installed"),
+        }
+        if found.compose.is_none() {
+            print_warn!("The Docker Compose plugin is still not installed.");
+        }
+    }
 
+    if !matches!(systemd::is_active("docker.service"), Ok(true)) {
+        let _ = systemd::enable("docker.service");
+    }
+    docker::wait_for_daemon(Path::new(docker::SOCKET), Duration::from_secs(30))?;
+    print_ok!("Docker daemon is running.");
     Ok(())
 }
 
-// TODO
-fn check_rclone() -> anyhow::Result<(), anyhow::Error> {
-    // check if rclone is installed
-    // if not exits, do not abort, and install it
-    let rclone_version = Command::new("rclone").arg("--version").output().unwrap();
-
-    // if not installed, install rclone
-    if rclone_version.status.code().unwrap() != 0 {
-        let _ = match confirm_default_yes("Rclone is not installed, install it now?").unwrap() {
-            true => {
-                print_output!("Installing rclone...");
-                let child = Command::new("wget")
-                    .arg("-qO")
-                    .arg("./install.sh")
-                    .arg("https://rclone.org/install.sh")
-                    .spawn()
-                    .expect("failed to execute process");
-
-                let _ = child.wait_with_output().expect("failed to wait on child");
-
-                let region = get_region().unwrap();
-                if region == "cn" {
-                    replace_string_in_file(
-                        "./install.sh",
-                        "downloads.rclone.org",
-                        "casaos.oss-cn-shanghai.aliyuncs.com",
-                    );
-                } else {
-                    replace_string_in_file("./install.sh", "downloads.rclone.org", "get.casaos.io");
-                }
-
-                // chmod +x install.sh
-                let _ = Command::new("chmod")
-                    .arg("+x")
-                    .arg("./install.sh")
-                    .output()
-                    .expect("failed to execute process");
-
-                // ./install.sh
-                let _ = Command::new("./install.sh")
-                    .arg("install")
-                    .output()
-                    .expect("failed to execute process");
-
-                // rm install.sh
-                let _ = std::fs::remove_file("./install.sh");
-                Ok(())
+/// Install Docker from the distribution or Docker's repositories.
+async fn install_docker(
+    args: &DockerArgs,
+    config: &Config,
+    detect_region: bool,
+) -> anyhow::Result<(), anyhow::Error> {
+    let (id, manager) = detect_manager()?;
+    match args
+        .docker_from
+        .unwrap_or_else(|| docker::Source::default_for(&id))
+    {
+        docker::Source::Distro => {
+            let (engine, compose) = docker::distro_packages(manager);
+            let mut system = package::System::new(manager);
+            system.refresh()?;
+            let compose = docker::first_available(&system, compose)?;
+            if compose.is_none() && docker::script_supports(&id) {
+                print_warn!(
+                    "{} does not package Docker Compose v2, installing Docker from Docker's repositories instead",
+                    id
+                );
+                return install_docker_from_script(args, config, detect_region).await;
             }
-            false => Err(anyhow::anyhow!("Installation cancelled.")),
-        };
+            let packages = [Some(engine), compose]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>();
+            print_info!("Installing {}...", packages.join(", "));
+            let outcome = package::ensure(&mut system, &packages)?;
+            if !outcome.unavailable.is_empty() {
+                print_warn!("Could not find {}", outcome.unavailable.join(", "));
+            }
+            package::record(Path::new(DEPENDENCIES_RECORD), manager, &outcome.installed)?;
+        }
+        docker::Source::Script => install_docker_from_script(args, config, detect_region).await?,
     }
+    Ok(())
+}
 
-    // if installed, check rclone version
-    print_info!("Checking rclone version...");
+/// Install Docker with the bundled script, from `--docker-mirror`, the configured
+/// mirror, Aliyun for mainland China or else Docker's own repositories.
+async fn install_docker_from_script(
+    args: &DockerArgs,
+    config: &Config,
+    detect_region: bool,
+) -> anyhow::Result<(), anyhow::Error> {
+    let mut url = args.docker_mirror.clone().or(config.docker_mirror.clone());
+    if url.is_none() && detect_region {
+        let client = release::http_client()?;
+        if mirror::detect_region(&client).await.as_deref() == Some("CN") {
+            url = Some(docker::ALIYUN_DOWNLOAD_URL.to_string());
+        }
+    }
+    let url = url.unwrap_or_else(|| docker::DOWNLOAD_URL.to_string());
+    print_info!("Installing Docker from {}...", style(&url).bold());
+    docker::install_with_script(&url)
+}
 
-    Ok(())
+/// Distribution id and package manager of this machine.
+fn detect_manager() -> anyhow::Result<(String, Manager), anyhow::Error> {
+    let release = linux_os_release()?;
+    let id = release.id.unwrap_or_default();
+    let manager = Manager::detect(&id, &release.id_like.unwrap_or_default())
+        .with_context(|| format!("No supported package manager for {}", id))?;
+    Ok((id, manager))
 }
 
 // TODO
+#[allow(dead_code)]
 fn configuraion_addon() -> anyhow::Result<(), anyhow::Error> {
     Ok(())
 }
 
-async fn download_and_install_casaos(
-    download_domain: String,
-    arch: String,
+/// Resolve the version and download url of every package from the release index.
+async fn resolve_packages(
+    mirrors: &[String],
+    arch: &str,
+    args: &ReleaseArgs,
+) -> anyhow::Result<Vec<Package>, anyhow::Error> {
+    let selection = args.selection();
+    let client = release::http_client()?;
+    let releases =
+        release::fetch_releases(&client, mirrors, &CASA_PACKAGES, selection.index.as_deref())
+            .await?;
+    release::resolve(&releases, &CASA_PACKAGES, mirrors, arch, &selection)
+}
+
+/// Take the packages for `arch` from an offline bundle into `tmp_dir` and verify them.
+/// Returns the directory holding the bundle contents along with the packages.
+fn open_bundle(
+    path: &Path,
+    arch: &str,
+    tmp_dir: &Path,
+    cmd: &Args,
+) -> anyhow::Result<(PathBuf, Vec<Package>), anyhow::Error> {
+    let dir = bundle::open(path, &tmp_dir.join("bundle"))?;
+    let packages = bundle::packages(&dir, arch)?;
+
+    let mut signatures = vec![];
+    for p in packages.iter() {
+        let file = tmp_dir.join(&p.file_name);
+        let path = p.path.as_ref().context("Bundled package without a path")?;
+        std::fs::copy(path, &file)
+            .with_context(|| format!("Failed to copy {} from the bundle", p.file_name))?;
+        print_info!("{} {}", p.name, style(&p.version).bold());
+
+        if !cmd.release.skip_verify {
+            let Some(expected) = &p.sha256 else {
+                anyhow::bail!(
+                    "No checksum recorded in the bundle for {} ({}), use --skip-verify to install anyway",
+                    p.name,
+                    p.file_name
+                );
+            };
+            checksum::verify(&file, p, expected)?;
+            print_ok!("{} Verified", p.file_name);
+        }
+        signatures.push(
+            p.signature_path()
+                .and_then(|path| std::fs::read_to_string(path).ok()),
+        );
+    }
+    if cmd.release.skip_verify {
+        print_warn!("Skipping checksum verification.");
+    }
+
+    verify_signatures(&packages, signatures, tmp_dir, cmd, TRUSTED_KEYS)?;
+    Ok((dir, packages))
+}
+
+/// Load the docker images shipped in a bundle.
+fn load_images(dir: &Path) {
+    for image in bundle::images(dir) {
+        print_info!("Loading image {}...", style(image.display()).bold());
+        match oplog::run(Command::new("docker").arg("load").arg("-i").arg(&image)) {
+            Ok(status) if status.success() => print_ok!("{} Loaded", image.display()),
+            _ => print_warn!("Failed to load image {}", image.display()),
+        }
+    }
+}
+
+/// Download all packages into `tmp_dir` and verify them.
+async fn download_casaos(
+    packages: &mut [Package],
+    tmp_dir: &Path,
+    cmd: &Args,
 ) -> anyhow::Result<(), anyhow::Error> {
-    let client = Client::new();
-    // 在/tmp目录下创建casaos目录
-    let tmp_dir = tempfile::tempdir()?.path().join("casaos");
-    // create tmp dir
-    std::fs::create_dir_all(&tmp_dir)?;
-    let packages = vec![
-        "${CASA_DOWNLOAD_DOMAIN}CasaOS-Gateway/releases/download/v0.4.2/linux-${TARGET_ARCH}-casaos-gateway-v0.4.2.tar.gz",
-        "${CASA_DOWNLOAD_DOMAIN}CasaOS-MessageBus/releases/download/v0.4.2/linux-${TARGET_ARCH}-casaos-message-bus-v0.4.2.tar.gz",
-        "${CASA_DOWNLOAD_DOMAIN}CasaOS-UserService/releases/download/v0.4.2/linux-${TARGET_ARCH}-casaos-user-service-v0.4.2.tar.gz",
-        "${CASA_DOWNLOAD_DOMAIN}CasaOS-LocalStorage/releases/download/v0.4.3/linux-${TARGET_ARCH}-casaos-local-storage-v0.4.3.tar.gz",
-        "${CASA_DOWNLOAD_DOMAIN}CasaOS-AppManagement/releases/download/v0.4.3/linux-${TARGET_ARCH}-casaos-app-management-v0.4.3.tar.gz",
-        "${CASA_DOWNLOAD_DOMAIN}CasaOS/releases/download/v0.4.3-1/linux-${TARGET_ARCH}-casaos-v0.4.3-1.tar.gz",
-        "${CASA_DOWNLOAD_DOMAIN}CasaOS-CLI/releases/download/v0.4.3-alpha2/linux-${TARGET_ARCH}-casaos-cli-v0.4.3-alpha2.tar.gz",
-        "${CASA_DOWNLOAD_DOMAIN}CasaOS-UI/releases/download/v0.4.3/linux-all-casaos-v0.4.3.tar.gz"
-    ].into_iter()
-    .map(|x| x.replace("${CASA_DOWNLOAD_DOMAIN}", &download_domain))
-    .map(|x| x.replace("${TARGET_ARCH}", &arch))
-    .collect::<Vec<String>>();
-
-    let sizes = {
-        let mut sizes: Vec<String> = vec![];
-        for p in packages.iter() {
-            let response = client.head(p).send().await?;
-            if response.status().is_success() {
-                let size = response
-                    .headers()
-                    .get(CONTENT_LENGTH)
-                    .and_then(|ct_len| ct_len.to_str().ok())
-                    .and_then(|ct_len| ct_len.parse().ok())
-                    .unwrap_or(0);
-                sizes.push(size.to_string());
-            }
+    let client = release::http_client()?;
+    download_packages(&client, packages, tmp_dir, &cmd.release.download.options()).await?;
+    verify_checksums(&client, packages, tmp_dir, cmd.release.skip_verify).await?;
+
+    let mut signatures = vec![];
+    for p in packages.iter() {
+        signatures.push(signature::fetch_signature(&client, p).await?);
+    }
+    verify_signatures(packages, signatures, tmp_dir, cmd, TRUSTED_KEYS)
+}
+
+/// Download the rclone release archive for `arch` into `tmp_dir`, verify it
+/// against rclone's published digests and extract the binary.
+async fn download_rclone(
+    mirrors: &[String],
+    arch: &str,
+    tmp_dir: &Path,
+    args: &ReleaseArgs,
+) -> anyhow::Result<PathBuf, anyhow::Error> {
+    let client = release::http_client()?;
+    let mut packages = [rclone::package(mirrors, arch, rclone::VERSION)];
+    download_packages(&client, &mut packages, tmp_dir, &args.download.options()).await?;
+    verify_rclone(&client, &packages[0], tmp_dir, args.skip_verify).await?;
+
+    let binary = tmp_dir.join("rclone");
+    rclone::extract(&tmp_dir.join(&packages[0].file_name), &binary)?;
+    Ok(binary)
+}
+
+/// Check a downloaded rclone archive against its `SHA256SUMS`.
+/// Returns the SHA-256 of the archive.
+pub async fn verify_rclone(
+    client: &reqwest::Client,
+    package: &Package,
+    dir: &Path,
+    skip_verify: bool,
+) -> anyhow::Result<String, anyhow::Error> {
+    let file = dir.join(&package.file_name);
+    if skip_verify {
+        return checksum::sha256_file(&file);
+    }
+    match rclone::expected_digest(client, package, rclone::DOWNLOADS)
+        .await
+        .context("Could not get the rclone checksum, use --skip-verify to install anyway")?
+    {
+        Some(expected) => {
+            checksum::verify(&file, package, &expected)?;
+            print_ok!("{} Verified", package.file_name);
+            Ok(expected)
         }
-        sizes
+        None => anyhow::bail!(
+            "No checksum published for rclone ({}), use --skip-verify to install anyway",
+            package.file_name
+        ),
+    }
+}
+
+/// Take the rclone archive for `arch` from the bundle in `dir` and extract the binary.
+/// Returns `None` when the bundle has none.
+fn bundle_rclone(
+    dir: &Path,
+    arch: &str,
+    tmp_dir: &Path,
+    skip_verify: bool,
+) -> anyhow::Result<Option<PathBuf>, anyhow::Error> {
+    let Some(p) = bundle::rclone(dir, arch)? else {
+        return Ok(None);
     };
+    let file = tmp_dir.join(&p.file_name);
+    let path = p.path.as_ref().context("Bundled package without a path")?;
+    std::fs::copy(path, &file)
+        .with_context(|| format!("Failed to copy {} from the bundle", p.file_name))?;
+    if !skip_verify {
+        let Some(expected) = &p.sha256 else {
+            anyhow::bail!(
+                "No checksum recorded in the bundle for rclone ({}), use --skip-verify to install anyway",
+                p.file_name
+            );
+        };
+        checksum::verify(&file, &p, expected)?;
+        print_ok!("{} Verified", p.file_name);
+    }
+
+    let binary = tmp_dir.join("rclone");
+    rclone::extract(&file, &binary)?;
+    Ok(Some(binary))
+}
 
+/// Download the package tarballs into `dir`.
+/// A package is retried and then fetched from the next mirror when it fails,
+/// and its url is updated to the mirror that served it.
+pub async fn download_packages(
+    client: &reqwest::Client,
+    packages: &mut [Package],
+    dir: &Path,
+    options: &download::Options,
+) -> anyhow::Result<(), anyhow::Error> {
     let spinner_style = ProgressStyle::with_template(
         "{prefix:.bold} {spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} {msg}",
-    )
-    .unwrap()
+    )?
     .progress_chars("#>-");
 
     let m = MultiProgress::new();
-
-    let handles: Vec<_> = (0..packages.len())
-        .map(|i| {
-            let package = packages[i].clone();
-            let size = sizes[i].clone();
-            let name = package.rsplit('/').next().unwrap().to_string();
-            let pb = m.add(ProgressBar::new(size.parse::<u64>().unwrap()));
+    let jobs = packages
+        .iter()
+        .map(|p| {
+            let pb = m.add(ProgressBar::new(0));
             pb.set_style(spinner_style.clone());
-            pb.set_prefix(format!("Downloading {}\n", name));
-            let mut request = client.get(package);
-            let file = tmp_dir.as_path().join(name);
-            // Download file
-            tokio::spawn(async move {
-                if file.exists() {
-                    let size = file.metadata().unwrap().len().saturating_sub(1);
-                    request = request.header(RANGE, format!("bytes={}-", size));
-                    pb.inc(size);
-                }
-                let mut source = request.send().await.unwrap();
-                let mut dest = OpenOptions::new()
-                    .create(true)
-                    .append(true)
-                    .open(&file)
-                    .await
-                    .unwrap();
-                while let Some(chunk) = source.chunk().await.unwrap() {
-                    dest.write_all(&chunk).await.unwrap();
-                    pb.inc(chunk.len() as u64);
-                }
-                pb.finish_with_message("Downloaded");
-            })
+            pb.set_prefix(format!("Downloading {}\n", p.file_name));
+            download::Job {
+                urls: std::iter::once(p.url.clone())
+                    .chain(p.fallback_urls.iter().cloned())
+                    .collect(),
+                dest: dir.join(&p.file_name),
+                progress: pb,
+            }
         })
         .collect();
 
-    for handle in handles {
-        let _ = handle.await;
-    }
-
-    for p in packages.iter() {
-        print_info!("Extracting {}...", style(format!("{}", p)).bold());
-        let name = p.rsplit('/').next().unwrap().to_string();
-        let file = tmp_dir.as_path().join(name.clone());
-        let mut archive = Archive::new(GzDecoder::new(File::open(&file).unwrap()));
-        if archive.unpack(tmp_dir.as_path()).is_err() {
-            print_error!("Failed to extract {}", name);
-        } else {
-            print_ok!("{} Extracted", name);
+    let results = download::download_all(client, jobs, options).await;
+    let mut failed = vec![];
+    for (p, result) in packages.iter_mut().zip(results) {
+        match result {
+            Ok(url) => p.url = url,
+            Err(e) => failed.push(format!("{}: {:#}", p.file_name, e)),
         }
     }
-    let services = CASA_SERVICES.clone();
+    if !failed.is_empty() {
+        anyhow::bail!("Failed to download:\n  {}", failed.join("\n  "));
+    }
+    Ok(())
+}
 
-    let build_dir = tmp_dir.as_path().join("build");
-    if !build_dir.exists() {
-        print_error!("Failed to find build directory");
+/// Check the downloaded packages against their published digests.
+/// Returns the SHA-256 of every package file.
+pub async fn verify_checksums(
+    client: &reqwest::Client,
+    packages: &[Package],
+    dir: &Path,
+    skip_verify: bool,
+) -> anyhow::Result<Vec<String>, anyhow::Error> {
+    if skip_verify {
+        print_warn!("Skipping checksum verification.");
     }
 
-    // stop services
-    for service in services {
-        print_info!("Stopping {}...", style(format!("{}", service)).bold());
-        if let Ok(true) = systemd::exists(service) {
-            if let Ok(true) = systemd::disable(service) {
-                print_ok!("{} Stopped", service);
-            } else {
-                print_warn!("Failed to stop {}", service);
+    let mut digests = vec![];
+    for p in packages.iter() {
+        let file = dir.join(&p.file_name);
+        if skip_verify {
+            digests.push(checksum::sha256_file(&file)?);
+            continue;
+        }
+        match checksum::expected_digest(client, p).await? {
+            Some(expected) => {
+                checksum::verify(&file, p, &expected)?;
+                print_ok!("{} Verified", p.file_name);
+                digests.push(expected);
             }
-        } else {
-            print_warn!("Service {} does not exist.", service);
+            None => anyhow::bail!(
+                "No checksum published for {} ({}), use --skip-verify to install anyway",
+                p.name,
+                p.file_name
+            ),
         }
     }
+    Ok(digests)
+}
 
-    // check if migration script directory exists
-    let migration_script_dir = build_dir.join("scripts/migration/script.d");
-    if !migration_script_dir.exists() {
-        print_error!("Failed to find migration script directory");
-    }
-
-    // execute migration scripts
-    for entry in WalkDir::new(migration_script_dir)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let path = entry.path();
-        if path.is_file() && path.extension().unwrap_or_default() == "sh" {
-            print_info!(
-                "Running migration script {}...",
-                style(format!("{}", path.display())).bold()
-            );
-            let status = Command::new("bash").arg(path).status().unwrap();
-            if !status.success() {
-                print_error!("Failed to run migration script");
+/// Extract the packages into `tmp_dir` and record which package provided each sysroot file.
+fn extract_packages(
+    packages: &[Package],
+    tmp_dir: &Path,
+) -> anyhow::Result<manifest::Owners, anyhow::Error> {
+    let sysroot = Path::new("build").join(plan::SYSROOT_DIR);
+    let mut owners = manifest::Owners::new();
+    for p in packages.iter() {
+        print_info!("Extracting {}...", style(&p.file_name).bold());
+        let file = File::open(tmp_dir.join(&p.file_name))
+            .with_context(|| format!("Failed to open {}", p.file_name))?;
+        let mut archive = Archive::new(GzDecoder::new(file));
+        for entry in archive
+            .entries()
+            .with_context(|| format!("Failed to extract {}", p.file_name))?
+        {
+            let mut entry = entry.with_context(|| format!("Failed to extract {}", p.file_name))?;
+            let path: PathBuf = entry
+                .path()?
+                .components()
+                .filter(|c| !matches!(c, std::path::Component::CurDir))
+                .collect();
+            entry
+                .unpack_in(tmp_dir)
+                .with_context(|| format!("Failed to extract {}", p.file_name))?;
+            if let Ok(path) = path.strip_prefix(&sysroot) {
+                if !entry.header().entry_type().is_dir() {
+                    // A later package overwrites the files of an earlier one.
+                    owners.insert(path.to_path_buf(), (p.name.clone(), p.version.clone()));
+                }
             }
         }
+        print_ok!("{} Extracted", p.file_name);
     }
+    if !tmp_dir.join("build").exists() {
+        anyhow::bail!("Failed to find build directory");
+    }
+    Ok(owners)
+}
 
-    print_info!("Installing CasaOS...");
-    let sysroot_dir = build_dir.join("sysroot");
-    if !sysroot_dir.exists() {
-        print_error!("Failed to find sysroot directory");
+/// Which scripts may run, from the command line and the config file.
+fn script_policy(cmd: &Args) -> anyhow::Result<script::Policy, anyhow::Error> {
+    let config = Config::load()?;
+    Ok(script::Policy {
+        allow: [cmd.allowed_scripts.clone(), config.allowed_scripts].concat(),
+        deny: [cmd.denied_scripts.clone(), config.denied_scripts].concat(),
+    })
+}
+
+/// Migration and setup scripts of the extracted packages.
+fn package_scripts(tmp_dir: &Path) -> Vec<PathBuf> {
+    let build_dir = tmp_dir.join("build");
+    [
+        migration::scripts(&build_dir.join(plan::MIGRATION_SCRIPT_DIR)),
+        plan::scripts(&build_dir.join(plan::SETUP_SCRIPT_DIR)),
+    ]
+    .concat()
+}
+
+/// How the package scripts are run.
+struct Scripts {
+    migrations: migration::Runner,
+    /// Print each setup script before running it.
+    show: bool,
+}
+
+/// Migrations from the installed CasaOS version to the one being installed.
+fn scripts(cmd: &Args, packages: &[Package], root: Option<&Path>) -> Scripts {
+    let root = root.unwrap_or(Path::new("/")).to_path_buf();
+    let from_version = Manifest::load(&root)
+        .ok()
+        .flatten()
+        .and_then(|m| {
+            m.components()
+                .into_iter()
+                .find(|(name, _)| name == "CasaOS")
+                .map(|(_, version)| version)
+        })
+        .unwrap_or_default();
+    let to_version = packages
+        .iter()
+        .find(|p| p.name == "CasaOS")
+        .map(|p| p.version.clone())
+        .unwrap_or_default();
+    Scripts {
+        migrations: migration::Runner {
+            root,
+            from_version,
+            to_version,
+            timeout: Duration::from_secs(cmd.script_timeout),
+            show: cmd.show_scripts,
+        },
+        show: cmd.show_scripts,
     }
+}
 
-    // Generate manifest for uninstallation
-    let manifest_file = build_dir.join("sysroot/var/lib/casaos/manifest");
-    let mut manifest = File::create(manifest_file)?;
-    for entry in WalkDir::new(sysroot_dir.clone())
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let path = entry.path();
-        if path.is_file() {
-            // write file path to manifest
-            manifest.write_all(format!("{}\n", path.to_str().unwrap()).as_bytes())?;
+/// Install the extracted packages onto the system, or into `root`, as a single transaction.
+/// When a step fails, the overwritten files are restored from `backup_dir`
+/// and the services are put back the way they were.
+fn install_casaos(
+    tmp_dir: &Path,
+    rclone_binary: Option<&Path>,
+    data_dir: Option<&Path>,
+    owners: &manifest::Owners,
+    scripts: &Scripts,
+    backup_dir: &Path,
+    root: Option<&Path>,
+) -> anyhow::Result<(), anyhow::Error> {
+    let mut tx = Transaction::new(backup_dir, root)?;
+    match install_steps(
+        tmp_dir,
+        rclone_binary,
+        data_dir,
+        owners,
+        scripts,
+        root,
+        &mut tx,
+    ) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            print_warn!("Installation failed, rolling back...");
+            tx.rollback().print();
+            Err(e.context("Installation failed and was rolled back"))
         }
     }
+}
 
-    let options = fs_extra::dir::CopyOptions::new().overwrite(true);
-    for entry in WalkDir::new(sysroot_dir.clone())
-        .min_depth(1)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let path = entry.path();
-        let dest = Path::new("/").join(path.strip_prefix(sysroot_dir.clone()).unwrap());
-        if path.is_dir() {
-            if let Err(e) = fs_extra::dir::copy(path, "/", &options) {
-                print_error!("Failed to copy directory: {}", e);
-            } else {
-                print_ok!("Copied {} to {}", path.display(), dest.display());
-            }
+fn install_steps(
+    tmp_dir: &Path,
+    rclone_binary: Option<&Path>,
+    data_dir: Option<&Path>,
+    owners: &manifest::Owners,
+    scripts: &Scripts,
+    root: Option<&Path>,
+    tx: &mut Transaction,
+) -> anyhow::Result<(), anyhow::Error> {
+    // Another root is an image, nothing of it is running and its scripts can't run here.
+    let offline = root.is_some();
+    let root = root.unwrap_or(Path::new("/"));
+    let build_dir = tmp_dir.join("build");
+    let migration_script_dir = build_dir.join(plan::MIGRATION_SCRIPT_DIR);
+    let sysroot_dir = build_dir.join(plan::SYSROOT_DIR);
+    let setup_script_dir = build_dir.join(plan::SETUP_SCRIPT_DIR);
+    for dir in [&migration_script_dir, &sysroot_dir, &setup_script_dir] {
+        if !dir.exists() {
+            anyhow::bail!(
+                "Failed to find {} in the packages",
+                dir.strip_prefix(&build_dir)?.display()
+            );
         }
     }
 
-    // check if setup script directory exists
-    let setup_script_dir = build_dir.join("scripts/setup/script.d");
-    if !setup_script_dir.exists() {
-        print_error!("Failed to find setup script directory");
+    if offline {
+        print_warn!("Skipping migration scripts when installing into another root.");
+    } else {
+        stop_services(tx)?;
+        scripts.migrations.run(&migration_script_dir)?;
     }
-    // execute migration scripts
-    print_output!("Running setup scripts...");
-    for entry in WalkDir::new(setup_script_dir)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let path = entry.path();
-        if path.is_file() && path.extension().unwrap_or_default() == "sh" {
-            let status = Command::new("bash").arg(path).status().unwrap();
-            if !status.success() {
-                print_error!("Failed to run setup script");
+
+    print_info!("Installing CasaOS...");
+
+    let previous = Manifest::load(root).unwrap_or_else(|e| {
+        print_warn!("Ignoring the manifest of the previous install: {:#}", e);
+        None
+    });
+    let mut manifest = Manifest {
+        version: manifest::VERSION,
+        components: previous
+            .as_ref()
+            .map(|m| m.components().into_iter().collect())
+            .unwrap_or_default(),
+        files: vec![],
+    };
+    manifest.components.extend(owners.values().cloned());
+    // Set before copying so the manifest records the mode it ends up with.
+    let ui_events_reg_script = sysroot_dir.join("etc/casaos/start.d/register-ui-events.sh");
+    if ui_events_reg_script.exists() {
+        std::fs::set_permissions(&ui_events_reg_script, Permissions::from_mode(0o755))?;
+    }
+
+    let mut installed = 0;
+    for entry in WalkDir::new(&sysroot_dir).min_depth(1).sort_by_file_name() {
+        let entry = entry?;
+        let relative = entry.path().strip_prefix(&sysroot_dir)?;
+        let mut dest = root.join(relative);
+        if entry.file_type().is_dir() {
+            tx.create_dir_all(&dest)?;
+            continue;
+        }
+        let (component, version) = owners
+            .get(relative)
+            .cloned()
+            .unwrap_or_else(|| ("CasaOS".to_string(), String::new()));
+        let file = manifest::FileEntry::new(
+            entry.path(),
+            &Path::new("/").join(relative),
+            &component,
+            &version,
+        )?;
+        let kept = previous
+            .as_ref()
+            .and_then(|m| m.get(&file.path))
+            .filter(|old| file.config && config_modified(old, &dest));
+        if let Some(old) = kept {
+            // Keep the edited config, the packaged one is put next to it.
+            if file.path == Path::new("/").join(gateway::GATEWAY_INI) {
+                keep_gateway_port(entry.path(), &dest, tmp_dir, tx)?;
             }
+            manifest.files.push(old.clone());
+            let mut name = dest.file_name().unwrap_or_default().to_os_string();
+            name.push(".yacc-new");
+            dest.set_file_name(name);
+            print_warn!(
+                "{} was modified, the new version is installed as {}",
+                file.path.display(),
+                dest.display()
+            );
+            manifest.files.push(manifest::FileEntry {
+                path: Path::new("/").join(dest.strip_prefix(root)?),
+                ..file
+            });
+        } else {
+            manifest.files.push(file);
         }
+        tx.install_file(entry.path(), &dest)?;
+        installed += 1;
     }
+    print_ok!("Installed {} files", installed);
 
-    let ui_events_reg_script = Path::new("/etc/casaos/start.d/register-ui-events.sh");
+    if let Some(data_dir) = data_dir {
+        link_data_dir(tmp_dir, data_dir, root, tx)?;
+    }
 
-    // chmod +x /etc/casaos/start.d/register-ui-events.sh
-    let mut perms = std::fs::metadata(ui_events_reg_script)?.permissions();
-    perms.set_mode(0o755);
-    std::fs::set_permissions(ui_events_reg_script, perms)?;
+    if offline {
+        print_warn!("Skipping setup scripts, run them on the target system.");
+    } else {
+        print_output!("Running setup scripts...");
+        run_setup_scripts(&setup_script_dir, scripts)?;
+    }
 
     // let _ = configuraion_addon();
 
-    // let _ = check_rclone();
+    install_rclone(tmp_dir, rclone_binary, root, offline, &mut manifest, tx)?;
+    // Files of a previous install that were left alone, e.g. an rclone that was already there.
+    for file in previous.iter().flat_map(|m| m.files.iter()) {
+        if manifest.get(&file.path).is_none() && manifest::check(root, file).is_none() {
+            manifest.files.push(file.clone());
+        }
+    }
+
+    let manifest_file = tmp_dir.join("manifest.json");
+    std::fs::write(&manifest_file, serde_json::to_string_pretty(&manifest)?)?;
+    tx.install_file(&manifest_file, &root.join(manifest::MANIFEST_FILE))?;
 
     // Start and enable casaos services
-    for service in services {
-        print_info!("Starting {}...", style(format!("{}", service)).bold());
-        if let Ok(true) = systemd::enable(service) {
-            print_ok!("{}", style(format!("{} is enabled", service)));
-        } else {
-            print_error!("{} is not running, Please reinstall", service);
+    for service in CASA_SERVICES {
+        if *service == rclone::UNIT && !rclone::installed(root) {
+            print_warn!("rclone is not installed, not enabling {}.", service);
+            continue;
+        }
+        if offline {
+            if matches!(systemd::enable_in(root, service), Ok(true)) {
+                tx.service_enabled(service);
+                print_ok!("{} is enabled in {}", service, root.display());
+            } else {
+                print_warn!("Failed to enable {} in {}", service, root.display());
+            }
+            continue;
+        }
+        print_info!("Starting {}...", style(service).bold());
+        if !matches!(systemd::exists(service), Ok(true)) {
+            print_warn!("Service {} does not exist.", service);
+            continue;
+        }
+        let enabled = matches!(systemd::is_enabled(service), Ok(true));
+        if !systemd::enable(service)? {
+            anyhow::bail!("Failed to enable {}", service);
+        }
+        if !enabled {
+            tx.service_enabled(service);
         }
+        print_ok!("{}", style(format!("{} is enabled", service)));
     }
-    drop(tmp_dir);
 
     Ok(())
 }
 
-fn check_service_status() -> anyhow::Result<(), anyhow::Error> {
-    let services = CASA_SERVICES.clone();
+/// Carry the port of the packaged gateway config, which holds `--port`, over to an
+/// edited one that is kept instead.
+fn keep_gateway_port(
+    packaged: &Path,
+    kept: &Path,
+    tmp_dir: &Path,
+    tx: &mut Transaction,
+) -> anyhow::Result<(), anyhow::Error> {
+    let Some(port) = gateway::port(packaged) else {
+        return Ok(());
+    };
+    if gateway::port(kept).unwrap_or(gateway::DEFAULT_PORT) == port {
+        return Ok(());
+    }
+    let edited = tmp_dir.join("gateway.ini");
+    std::fs::copy(kept, &edited)?;
+    gateway::set_port(&edited, port)?;
+    tx.install_file(&edited, kept)?;
+    print_info!("Set the gateway port {} in {}", port, kept.display());
+    Ok(())
+}
 
-    for service in services {
-        print_info!("Checking {}...", style(format!("{}", service)).bold());
-        if let Ok(true) = systemd::exists(service) {
-            if let Ok(true) = systemd::is_active(service) {
-                print_ok!("{}", style(format!("{} is running", service)));
-            } else {
-                print_error!("{} is not running, Please reinstall", service);
+/// Whether the config at `dest` was edited since `old` installed it.
+fn config_modified(old: &manifest::FileEntry, dest: &Path) -> bool {
+    old.sha256.is_some()
+        && manifest::check(
+            Path::new("/"),
+            &manifest::FileEntry {
+                path: dest.to_path_buf(),
+                ..old.clone()
+            },
+        ) == Some(manifest::Problem::Modified)
+}
+
+/// Create the data directory and link `/DATA` to it when it is elsewhere.
+fn link_data_dir(
+    tmp_dir: &Path,
+    data_dir: &Path,
+    root: &Path,
+    tx: &mut Transaction,
+) -> anyhow::Result<(), anyhow::Error> {
+    tx.create_dir_all(&root.join(data_dir.strip_prefix("/")?))?;
+    if data_dir == Path::new(DATA_DIR) {
+        return Ok(());
+    }
+    let link = root.join(DATA_DIR.trim_start_matches('/'));
+    match std::fs::read_link(&link) {
+        Ok(target) if target == data_dir => return Ok(()),
+        _ if link.symlink_metadata().is_ok() => {
+            print_warn!(
+                "{} already exists, CasaOS keeps its data there instead of {}",
+                DATA_DIR,
+                data_dir.display()
+            );
+            return Ok(());
+        }
+        _ => {}
+    }
+    let tmp_link = tmp_dir.join("DATA");
+    std::os::unix::fs::symlink(data_dir, &tmp_link)?;
+    tx.install_file(&tmp_link, &link)?;
+    print_ok!("Linked {} to {}", DATA_DIR, data_dir.display());
+    Ok(())
+}
+
+/// Install the rclone binary and create its unit if no package provides one.
+fn install_rclone(
+    tmp_dir: &Path,
+    binary: Option<&Path>,
+    root: &Path,
+    offline: bool,
+    manifest: &mut Manifest,
+    tx: &mut Transaction,
+) -> anyhow::Result<(), anyhow::Error> {
+    if let Some(binary) = binary {
+        tx.install_file(binary, &root.join(rclone::BINARY))?;
+        manifest.files.push(manifest::FileEntry::new(
+            binary,
+            &Path::new("/").join(rclone::BINARY),
+            bundle::RCLONE,
+            rclone::VERSION,
+        )?);
+        manifest
+            .components
+            .insert(bundle::RCLONE.to_string(), rclone::VERSION.to_string());
+        print_ok!("rclone {} installed", rclone::VERSION);
+    }
+
+    let unit_missing = rclone::installed(root)
+        && !rclone::unit_exists(root)
+        && (offline || !matches!(systemd::exists(rclone::UNIT), Ok(true)));
+    if unit_missing {
+        let unit_file = tmp_dir.join(rclone::UNIT);
+        std::fs::write(&unit_file, rclone::UNIT_FILE)?;
+        tx.install_file(&unit_file, &root.join(rclone::UNIT_PATH))?;
+        manifest.files.push(manifest::FileEntry::new(
+            &unit_file,
+            &Path::new("/").join(rclone::UNIT_PATH),
+            bundle::RCLONE,
+            rclone::VERSION,
+        )?);
+        if !offline && !systemd::daemon_reload()? {
+            anyhow::bail!("Failed to reload systemd after creating {}", rclone::UNIT);
+        }
+        print_ok!("Created {}", rclone::UNIT);
+    }
+    Ok(())
+}
+
+/// Stop and disable the CasaOS services, remembering which ones were enabled.
+fn stop_services(tx: &mut Transaction) -> anyhow::Result<(), anyhow::Error> {
+    for service in CASA_SERVICES {
+        print_info!("Stopping {}...", style(service).bold());
+        if !matches!(systemd::exists(service), Ok(true)) {
+            print_warn!("Service {} does not exist.", service);
+            continue;
+        }
+        let enabled = matches!(systemd::is_enabled(service), Ok(true));
+        if systemd::disable(service)? {
+            if enabled {
+                tx.service_disabled(service);
             }
+            print_ok!("{} Stopped", service);
         } else {
-            print_error!("Service {} does not exist.", service);
+            print_warn!("Failed to stop {}", service);
         }
     }
     Ok(())
 }
 
-fn get_ip() {
-    // 读取config
-    let config = Ini::load_from_file("/etc/casaos/gateway.ini").unwrap();
-    let gateway_section = config.section(Some("gateway")).unwrap();
-    let port = gateway_section.get("port").unwrap();
-    // 获取port字段
+/// Run the setup scripts in order, with their output going to a log of this install.
+fn run_setup_scripts(dir: &Path, scripts: &Scripts) -> anyhow::Result<(), anyhow::Error> {
+    let log_dir = scripts.migrations.root.join(script::LOG_DIR);
+    std::fs::create_dir_all(&log_dir)?;
+    let log = log_dir.join(format!(
+        "setup-{}.log",
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs()
+    ));
+    let mut log_file =
+        File::create(&log).with_context(|| format!("Failed to create {}", log.display()))?;
+    print_info!("Logging setup script output to {}", log.display());
+
+    for path in plan::scripts(dir) {
+        let name = path
+            .strip_prefix(dir)
+            .unwrap_or(&path)
+            .display()
+            .to_string();
+        if scripts.show {
+            script::show(&path)?;
+        }
+        print_info!("Running setup script {}...", style(&name).bold());
+        writeln!(log_file, "==> {} ({})", name, checksum::sha256_file(&path)?)?;
+        let offset = log_file.metadata()?.len();
+        let status = script::run(&path, &[], &log_file, scripts.migrations.timeout)
+            .with_context(|| format!("Setup script {} failed, see {}", name, log.display()))?;
+        oplog::output(&name, Some(status), &script::tail(&log, offset, usize::MAX));
+        if !status.success() {
+            let code = match status.code() {
+                Some(code) => format!("exited with code {}", code),
+                None => format!("was killed, {}", status),
+            };
+            anyhow::bail!(
+                "Setup script {} {}, last lines of its output:\n{}\nThe full output is in {}",
+                name,
+                code,
+                script::tail(&log, offset, 10),
+                log.display()
+            );
+        }
+    }
+    Ok(())
+}
 
-    let output = Command::new("ls")
-        .arg("/sys/class/net/")
-        .arg("|")
-        .arg(r#"grep -v "$(ls /sys/devices/virtual/net/)"#)
-        .output()
-        .expect("Failed to execute command");
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let all_nic: Vec<&str> = stdout.split_whitespace().collect();
-
-    for nic in all_nic {
-        let output = Command::new("ifconfig")
-            .arg(nic)
-            .output()
-            .expect("Failed to execute command");
-
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let ip = stdout
-            .lines()
-            .filter(|line| {
-                line.contains("inet") && !line.contains("127.0.0.1") && !line.contains("inet6")
-            })
-            .flat_map(|line| line.split_whitespace().nth(1))
-            .map(|ip| ip.trim_start_matches("addr:"))
-            .next();
-
-        if let Some(ip) = ip {
-            if port == "80" {
-                print_output!("{}", style(format!("- http://{} ({})", ip, nic)).bold());
-            } else {
-                print_output!(
-                    "{}",
-                    style(format!("- http://{}:{} ({})", ip, port, nic)).bold()
-                );
+/// Verify the detached signatures of all packages against the trusted keys.
+/// Their migration and setup scripts run as root, so an unsigned bundle is
+/// refused unless `--allow-unsigned` is given. A bad signature always is.
+fn verify_signatures(
+    packages: &[Package],
+    signatures: Vec<Option<String>>,
+    tmp_dir: &Path,
+    cmd: &Args,
+    shipped_keys: &[&str],
+) -> anyhow::Result<(), anyhow::Error> {
+    let keys = signature::trusted_keys(shipped_keys, &cmd.trusted_keys)?;
+    if keys.is_empty() && !cmd.require_signatures {
+        print_warn!(
+            "No signing key is shipped with this yacc, package signatures are not checked\nUse --trusted-key to verify signatures or --require-signatures to refuse unsigned packages"
+        );
+        return Ok(());
+    }
+
+    let mut unsigned = vec![];
+    for (p, sig) in packages.iter().zip(signatures) {
+        match sig {
+            Some(sig) if !keys.is_empty() => {
+                signature::verify(&tmp_dir.join(&p.file_name), p, &sig, &keys)?;
+                print_ok!("{} Signature verified", p.file_name);
+            }
+            _ => unsigned.push(p.name.clone()),
+        }
+    }
+
+    if unsigned.is_empty() {
+        return Ok(());
+    }
+    if cmd.allow_unsigned {
+        print_warn!(
+            "Packages without a verifiable signature: {}",
+            unsigned.join(", ")
+        );
+        return Ok(());
+    }
+    if keys.is_empty() {
+        anyhow::bail!(
+            "No trusted signing key to verify {} with\nUse --trusted-key to add a signing key",
+            unsigned.join(", ")
+        )
+    }
+    anyhow::bail!(
+        "Refusing to run migration and setup scripts from unsigned packages: {}\nUse --trusted-key to add a signing key or --allow-unsigned to install anyway",
+        unsigned.join(", ")
+    )
+}
+
+/// Wait for the services to become ready and print how each of them did.
+/// A service that is not ready is reported, it does not fail the install.
+fn check_service_status(timeout: Duration) {
+    print_info!("Waiting for the services to become ready...");
+    let targets = readiness::targets(Path::new("/"), CASA_SERVICES, timeout);
+    let report = readiness::check(&targets);
+    report.print();
+    if !report.ready() {
+        print_warn!(
+            "Some services are not ready, check them with `systemctl status <service>` or `journalctl -u <service>`."
+        );
+    }
+}
+
+/// Print where the web UI can be reached.
+fn print_urls() {
+    let port =
+        gateway::port(&Path::new("/").join(gateway::GATEWAY_INI)).unwrap_or(gateway::DEFAULT_PORT);
+    match network::interfaces(port) {
+        Ok(interfaces) => {
+            for interface in interfaces {
+                for url in interface.urls.iter() {
+                    print_output!(
+                        "{}",
+                        style(format!("- {} ({})", url, interface.name)).bold()
+                    );
+                }
             }
         }
+        Err(e) => print_warn!("Failed to list the network interfaces: {}", e),
     }
 }
 
@@ -604,7 +1802,7 @@ fn welcome_banner() -> anyhow::Result<(), anyhow::Error> {
             .green()
             .bold()
     );
-    get_ip();
+    print_urls();
     print_output!(
         "{}",
         style("─────────────────────────────────────────────────────")
@@ -637,15 +1835,37 @@ fn welcome_banner() -> anyhow::Result<(), anyhow::Error> {
 
 #[cfg(test)]
 mod test {
-    #[test]
-    fn test_install_docker() {
-        let _ = super::check_docker();
-        // assert!(result.is_ok());
+    use super::*;
+    use crate::cli::{Cli, SubCommand};
+    use crate::utils::signature::fixture;
+    use clap::Parser;
+
+    fn default_args() -> Args {
+        match Cli::try_parse_from(["yacc", "install"]).unwrap().subcommand {
+            SubCommand::Install(args) => args,
+            _ => unreachable!(),
+        }
     }
 
     #[test]
-    fn test_install_rclone() {
-        let result = super::check_rclone();
-        assert!(result.is_ok());
+    fn test_verify_signatures() {
+        let dir = tempfile::tempdir().unwrap();
+        let package = Package::test("http://localhost");
+        std::fs::write(dir.path().join(&package.file_name), fixture::TARBALL).unwrap();
+        let packages = [package];
+        let signed = || vec![Some(fixture::SIGNATURE.to_string())];
+        let shipped = [fixture::PUBLIC_KEY.lines().nth(1).unwrap()];
+
+        let cmd = default_args();
+        assert!(verify_signatures(&packages, signed(), dir.path(), &cmd, &shipped).is_ok());
+        assert!(verify_signatures(&packages, vec![None], dir.path(), &cmd, &shipped).is_err());
+        assert!(verify_signatures(&packages, vec![None], dir.path(), &cmd, &[]).is_ok());
+
+        let cmd = Args {
+            require_signatures: true,
+            ..default_args()
+        };
+        assert!(verify_signatures(&packages, signed(), dir.path(), &cmd, &shipped).is_ok());
+        assert!(verify_signatures(&packages, signed(), dir.path(), &cmd, &[]).is_err());
     }
 }
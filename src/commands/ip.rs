@@ -0,0 +1,52 @@
+use crate::{
+    print_output, print_warn,
+    utils::{
+        gateway,
+        network::{self, Interface},
+        output::Format,
+    },
+};
+use serde::Serialize;
+use std::path::Path;
+
+/// Show the addresses the CasaOS web UI can be reached at
+#[derive(clap::Parser, Debug, Default)]
+pub struct Args {
+    /// Port of the gateway, read from gateway.ini by default
+    #[clap(long)]
+    port: Option<u16>,
+
+    /// Format of the list
+    #[clap(long, value_enum, default_value_t)]
+    output: Format,
+}
+
+#[derive(Serialize, Debug)]
+struct Addresses {
+    port: u16,
+    interfaces: Vec<Interface>,
+}
+
+pub async fn run(cmd: Args) -> anyhow::Result<(), anyhow::Error> {
+    let port = cmd.port.unwrap_or_else(|| {
+        gateway::port(&Path::new("/").join(gateway::GATEWAY_INI)).unwrap_or(gateway::DEFAULT_PORT)
+    });
+    let interfaces = network::interfaces(port)?;
+    match cmd.output {
+        Format::Json => print_output!(
+            "{}",
+            serde_json::to_string_pretty(&Addresses { port, interfaces })?
+        ),
+        Format::Text => {
+            if interfaces.is_empty() {
+                print_warn!("No network interface with an address found.");
+            }
+            for interface in interfaces {
+                for url in interface.urls {
+                    print_output!("{:<12} {}", interface.name, url);
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,219 @@
+use crate::{
+    print_output, print_warn,
+    utils::{
+        oplog::{self, Event, Record},
+        output::{self, Format},
+    },
+};
+use console::style;
+use serde::Serialize;
+
+/// Browse the logs of past install, update and uninstall runs
+#[derive(clap::Parser, Debug)]
+pub struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// List the logged operations, oldest first
+    List {
+        /// Format of the list
+        #[clap(long, value_enum, default_value_t)]
+        output: Format,
+    },
+    /// Show everything an operation did
+    Show {
+        /// ID of the operation, as listed by `yacc log list`
+        id: String,
+
+        /// Format of the log
+        #[clap(long, value_enum, default_value_t)]
+        output: Format,
+    },
+}
+
+/// One line of `yacc log list`.
+#[derive(Serialize, Debug)]
+struct Summary {
+    id: String,
+    operation: String,
+    /// Milliseconds since the epoch.
+    started: u64,
+    elapsed_ms: Option<u64>,
+    /// `None` while running or when yacc was killed.
+    success: Option<bool>,
+}
+
+fn summarize(id: String, records: &[Record]) -> Option<Summary> {
+    let first = records.first()?;
+    let Event::Start { operation, .. } = &first.event else {
+        return None;
+    };
+    let end = records.iter().rev().find_map(|r| match r.event {
+        Event::End {
+            success,
+            elapsed_ms,
+        } => Some((success, elapsed_ms)),
+        _ => None,
+    });
+    Some(Summary {
+        id,
+        operation: operation.clone(),
+        started: first.ts,
+        elapsed_ms: end.map(|(_, ms)| ms),
+        success: end.map(|(success, _)| success),
+    })
+}
+
+fn seconds(ms: u64) -> String {
+    format!("{:.1}s", ms as f64 / 1000.0)
+}
+
+pub async fn run(cmd: Args) -> anyhow::Result<(), anyhow::Error> {
+    match cmd.command {
+        Command::List { output } => list(output),
+        Command::Show { id, output } => show(&id, output),
+    }
+}
+
+fn list(format: Format) -> anyhow::Result<(), anyhow::Error> {
+    if format == Format::Json {
+        output::status_to_stderr();
+    }
+    let mut summaries = vec![];
+    for id in oplog::list()? {
+        let records = oplog::read(&id)?;
+        match summarize(id.clone(), &records) {
+            Some(summary) => summaries.push(summary),
+            None => print_warn!("Skipping {}, it is not an operation log.", id),
+        }
+    }
+
+    match format {
+        Format::Json => print_output!("{}", serde_json::to_string_pretty(&summaries)?),
+        Format::Text => {
+            if summaries.is_empty() {
+                print_output!("No operations logged in {}", oplog::log_dir().display());
+                return Ok(());
+            }
+            print_output!(
+                "{:<32} {:<10} {:<20} {:>9}  STATUS",
+                "ID",
+                "OPERATION",
+                "STARTED (UTC)",
+                "DURATION"
+            );
+            for s in summaries {
+                let status = match s.success {
+                    Some(true) => style("ok").green(),
+                    Some(false) => style("failed").red(),
+                    None => style("incomplete").yellow(),
+                };
+                print_output!(
+                    "{:<32} {:<10} {:<20} {:>9}  {}",
+                    s.id,
+                    s.operation,
+                    oplog::timestamp(s.started / 1000),
+                    s.elapsed_ms.map(seconds).unwrap_or_default(),
+                    status
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn show(id: &str, format: Format) -> anyhow::Result<(), anyhow::Error> {
+    let records = oplog::read(id)?;
+    if format == Format::Json {
+        print_output!("{}", serde_json::to_string_pretty(&records)?);
+        return Ok(());
+    }
+
+    let started = records.first().map(|r| r.ts).unwrap_or_default();
+    for record in records.iter() {
+        let at = style(format!(
+            "[+{:>7}]",
+            seconds(record.ts.saturating_sub(started))
+        ))
+        .dim();
+        match &record.event {
+            Event::Start {
+                operation,
+                args,
+                version,
+            } => print_output!(
+                "{} {} started {} UTC by yacc {}: yacc {}",
+                at,
+                style(operation).bold(),
+                oplog::timestamp(record.ts / 1000),
+                version,
+                args.join(" ")
+            ),
+            Event::Message { level, message } => {
+                print_output!("{} {:<6} {}", at, level.to_uppercase(), message)
+            }
+            Event::Step { name } => print_output!("{} ==> {}", at, style(name).bold()),
+            Event::StepDone { name, elapsed_ms } => {
+                print_output!("{} <== {} took {}", at, name, seconds(*elapsed_ms))
+            }
+            Event::Output {
+                command,
+                status,
+                output,
+            } => {
+                let status = status.map(|c| format!("exit {}", c)).unwrap_or_default();
+                print_output!("{} $ {} {}", at, command, style(status).dim());
+                for line in output.lines() {
+                    print_output!("{}     {}", " ".repeat(10), style(line).dim());
+                }
+            }
+            Event::End {
+                success,
+                elapsed_ms,
+            } => print_output!(
+                "{} {} after {}",
+                at,
+                if *success {
+                    style("Succeeded").green()
+                } else {
+                    style("Failed").red()
+                },
+                seconds(*elapsed_ms)
+            ),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_summarize() {
+        let start = Record {
+            ts: 1000,
+            event: Event::Start {
+                operation: "install".to_string(),
+                args: vec![],
+                version: "0.1.4".to_string(),
+            },
+        };
+        let end = Record {
+            ts: 5000,
+            event: Event::End {
+                success: false,
+                elapsed_ms: 4000,
+            },
+        };
+        let summary = summarize("id".to_string(), &[start.clone(), end]).unwrap();
+        assert_eq!(summary.success, Some(false));
+        assert_eq!(summary.elapsed_ms, Some(4000));
+        let running = summarize("id".to_string(), &[start]).unwrap();
+        assert_eq!(running.success, None);
+        assert!(summarize("id".to_string(), &[]).is_none());
+    }
+}
@@ -1,3 +1,8 @@
+pub mod bundle;
+pub mod doctor;
 pub mod install;
+pub mod ip;
+pub mod log;
 pub mod uninstall;
 pub mod update;
+pub mod verify;
@@ -1,6 +1,10 @@
 use crate::consts::CASA_SERVICES;
-use crate::utils::confirm::confirm_default_no;
+use crate::utils::confirm::{confirm_default_no, confirm_default_yes};
+use crate::utils::manifest::{self, Manifest};
+use crate::utils::oplog;
 use crate::{print_error, print_info, print_output, print_warn};
+use anyhow::Context;
+use std::{path::Path, process::Command};
 
 /// Uninstall CasaOS
 #[derive(clap::Parser, Debug, Default)]
@@ -29,8 +33,9 @@ fn uninstall_casaos() -> anyhow::Result<(), anyhow::Error> {
     print_output!("This script will delete the containers you no longer use, and the CasaOS configuration files.");
 
     // stop and remove all containers
+    oplog::step("containers");
 
-    match confirm_default_no("Do you want delete all containers?") {
+    match confirm_default_no("delete_containers", "Do you want delete all containers?") {
         Ok(true) => {
             print_info!("Start deleting all containers.");
             uninstall_containers()?;
@@ -44,13 +49,8 @@ fn uninstall_casaos() -> anyhow::Result<(), anyhow::Error> {
     }
 
     // remove images
-    if dialoguer::Confirm::new()
-        .with_prompt("Do you want delete all images?")
-        .default(true)
-        .show_default(true)
-        .interact()
-        .unwrap()
-    {
+    oplog::step("images");
+    if confirm_default_yes("delete_images", "Do you want delete all images?")? {
         print_info!("Start deleting all images.");
         remove_images(true)?;
     } else {
@@ -59,33 +59,30 @@ fn uninstall_casaos() -> anyhow::Result<(), anyhow::Error> {
     }
 
     // stop and disable services
+    oplog::step("services");
     stop_and_remove_service()?;
 
     // remove casaos files
+    oplog::step("files");
     remove_files()?;
 
     Ok(())
 }
 
 fn detect_casaos() -> anyhow::Result<bool, anyhow::Error> {
-    Ok(std::path::Path::new("/usr/bin/casaos").exists())
+    Ok(Path::new("/usr/bin/casaos").exists()
+        || Path::new("/").join(manifest::MANIFEST_FILE).exists())
 }
 
 fn uninstall_containers() -> anyhow::Result<(), anyhow::Error> {
-    let command = std::process::Command::new("docker")
-        .args(["stop", "$(docker ps -aq)"])
-        .status()?
-        .success();
+    let command = oplog::run(Command::new("docker").args(["stop", "$(docker ps -aq)"]))?.success();
 
     if !command {
         print_warn!("Failed to stop containers.");
     }
 
     // remove all containers
-    let command = std::process::Command::new("docker")
-        .args(["rm", "$(docker ps -aq)"])
-        .status()?
-        .success();
+    let command = oplog::run(Command::new("docker").args(["rm", "$(docker ps -aq)"]))?.success();
 
     if !command {
         print_warn!("Failed to delete all containers.");
@@ -96,10 +93,7 @@ fn uninstall_containers() -> anyhow::Result<(), anyhow::Error> {
 
 fn remove_images(confirm: bool) -> anyhow::Result<(), anyhow::Error> {
     if !confirm {
-        let command = std::process::Command::new("docker")
-            .args(["image", "prune", "-af"])
-            .status()?
-            .success();
+        let command = oplog::run(Command::new("docker").args(["image", "prune", "-af"]))?.success();
 
         if !command {
             print_warn!("Failed to remove unused images.");
@@ -107,10 +101,8 @@ fn remove_images(confirm: bool) -> anyhow::Result<(), anyhow::Error> {
         return Ok(());
     }
 
-    let command = std::process::Command::new("docker")
-        .args(["rmi", "$(docker images -aq)"])
-        .status()?
-        .success();
+    let command =
+        oplog::run(Command::new("docker").args(["rmi", "$(docker images -aq)"]))?.success();
 
     if !command {
         print_warn!("Failed to remove all images.");
@@ -124,20 +116,14 @@ fn stop_and_remove_service() -> anyhow::Result<(), anyhow::Error> {
 
     for service in services {
         print_info!("Stopping {} ...", service);
-        let command = std::process::Command::new("systemctl")
-            .args(["stop", service])
-            .status()?
-            .success();
+        let command = oplog::run(Command::new("systemctl").args(["stop", service]))?.success();
 
         if !command {
             print_warn!("Failed to stop service: {}", service);
         }
 
         print_info!("Disabling {} ...", service);
-        let command = std::process::Command::new("systemctl")
-            .args(["disable", service])
-            .status()?
-            .success();
+        let command = oplog::run(Command::new("systemctl").args(["disable", service]))?.success();
 
         if !command {
             print_warn!("Failed to disable service: {}", service);
@@ -148,46 +134,73 @@ fn stop_and_remove_service() -> anyhow::Result<(), anyhow::Error> {
 }
 
 fn remove_files() -> anyhow::Result<(), anyhow::Error> {
+    let root = Path::new("/");
+    match Manifest::load(root)? {
+        Some(manifest) => {
+            print_info!("Removing {} installed files ...", manifest.files.len());
+            for file in manifest.files.iter() {
+                remove(&file.path)?;
+                prune_empty_dirs(&file.path)?;
+            }
+        }
+        None => print_warn!(
+            "No {} found, removing the files of older CasaOS versions.",
+            manifest::MANIFEST_FILE
+        ),
+    }
+
+    // State created at runtime, and files installed before there was a manifest.
     let files = vec![
         "/usr/lib/systemd/system/casaos.service",
-        "lib/systemd/system/casaos.service",
+        "/lib/systemd/system/casaos.service",
         "/etc/systemd/system/casaos.service",
-        "/etc/casaos",
         "/etc/udev/rules.d/11-usb-mount.rules",
         "/etc/systemd/system/usb-mount@.service",
         "/usr/local/bin/casaos",
         "/etc/casaos.conf",
-        "/var/lib/casaos/[0-9]*",
-        "/var/lib/casaos/db",
-        "/var/lib/casaos/*.db",
-        "/var/lib/casaos/www",
-        "/var/lib/casaos/migration",
         "/usr/share/casaos",
         "/var/log/casaos",
         "/etc/casaos",
         "/var/run/casaos",
         "/usr/bin/casaos-uninstall",
+        "/var/lib/casaos",
     ];
     for file in files {
-        std::fs::remove_dir_all(file)?;
+        remove(Path::new(file))?;
     }
 
-    let manifest = std::fs::read_to_string("/etc/casaos/manifest")?;
-    for line in manifest.lines() {
-        std::fs::remove_dir_all(line)?;
+    if confirm_default_yes("delete_app_data", "Do you want delete all app data?")? {
+        remove(Path::new("/DATA/AppData"))?;
     }
-    std::fs::remove_file("/var/lib/casaos/manifest")?;
-    std::fs::remove_dir_all("/var/lib/casaos")?;
-
-    if dialoguer::Confirm::new()
-        .with_prompt("Do you want delete all app data?")
-        .default(true)
-        .show_default(true)
-        .interact()
-        .unwrap()
-    {
-        std::fs::remove_dir_all("/DATA/AppData")?;
+
+    Ok(())
+}
+
+/// Remove a file, link or directory, if it is still there.
+fn remove(path: &Path) -> anyhow::Result<(), anyhow::Error> {
+    let result = match std::fs::symlink_metadata(path) {
+        Ok(m) if m.is_dir() => std::fs::remove_dir_all(path),
+        Ok(_) => std::fs::remove_file(path),
+        Err(e) => Err(e),
+    };
+    match result {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("Failed to remove {}", path.display()))
+        }
+        _ => Ok(()),
     }
+}
 
+/// Remove the CasaOS directories that `path` leaves empty, e.g. `/usr/share/casaos`.
+fn prune_empty_dirs(path: &Path) -> anyhow::Result<(), anyhow::Error> {
+    for dir in path.ancestors().skip(1) {
+        let owned = dir
+            .file_name()
+            .is_some_and(|n| n.to_string_lossy().to_lowercase().contains("casaos"));
+        if !owned || !dir.is_dir() || std::fs::read_dir(dir)?.next().is_some() {
+            break;
+        }
+        std::fs::remove_dir(dir)?;
+    }
     Ok(())
 }
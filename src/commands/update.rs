@@ -1,12 +1,69 @@
-use crate::print_output;
+use crate::{
+    commands::install,
+    print_info, print_ok, print_warn,
+    utils::{manifest::Manifest, release::Version},
+};
+use console::style;
+use std::path::Path;
 
+/// Update CasaOS to the newest release, installing only when a component is newer
+/// than the installed one recorded in the installation manifest
 #[derive(clap::Parser, Debug, Default)]
+#[group(id = "update")]
 pub struct Args {
-    #[clap(short, default_value = "false")]
-    alpha: bool,
+    /// Only show which components would be updated
+    #[clap(long, default_value = "false")]
+    check: bool,
+
+    #[clap(flatten)]
+    install: install::Args,
 }
 
-pub async fn run(_cmd: Args) -> anyhow::Result<(), anyhow::Error> {
-    print_output!("Update CasaOS successfully.");
-    Ok(())
+pub async fn run(mut cmd: Args) -> anyhow::Result<(), anyhow::Error> {
+    if cmd.install.is_bundle() {
+        return install::run(cmd.install).await;
+    }
+
+    let root = cmd.install.root().unwrap_or(Path::new("/")).to_path_buf();
+    let installed = match Manifest::load(&root)? {
+        Some(manifest) => manifest.components(),
+        None => {
+            print_warn!("No installation manifest found, the installed versions are unknown.");
+            vec![]
+        }
+    };
+
+    print_info!("Resolving CasaOS versions...");
+    let packages = install::latest_packages(&mut cmd.install).await?;
+    let mut outdated = 0;
+    for package in packages.iter() {
+        let current = installed
+            .iter()
+            .find(|(name, _)| *name == package.name)
+            .map(|(_, version)| version.as_str());
+        match current {
+            Some(current) if Version::parse(current) >= Version::parse(&package.version) => {
+                print_info!("{} {} is up to date", package.name, current);
+            }
+            _ => {
+                outdated += 1;
+                print_info!(
+                    "{} {} -> {}",
+                    package.name,
+                    current.unwrap_or("unknown"),
+                    style(&package.version).bold()
+                );
+            }
+        }
+    }
+
+    if outdated == 0 {
+        print_ok!("CasaOS is up to date.");
+        return Ok(());
+    }
+    if cmd.check {
+        print_info!("{} components can be updated.", outdated);
+        return Ok(());
+    }
+    install::run(cmd.install).await
 }
@@ -0,0 +1,101 @@
+use crate::{
+    print_error, print_info, print_ok, print_output, print_warn,
+    utils::{
+        manifest::{self, Manifest, Problem},
+        output::{self, Format},
+    },
+};
+use console::style;
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// Check the installed files against the installation manifest.
+/// Exits with 1 when a file is missing or was changed, edited config files are only reported
+#[derive(clap::Parser, Debug, Default)]
+pub struct Args {
+    /// Verify the CasaOS installed into this directory instead of /
+    #[clap(long, value_name = "PATH")]
+    root: Option<PathBuf>,
+
+    /// Format of the report
+    #[clap(long, value_enum, default_value_t)]
+    output: Format,
+}
+
+#[derive(Serialize, Debug)]
+struct Finding {
+    path: PathBuf,
+    component: String,
+    problem: Problem,
+    config: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct Report {
+    files: usize,
+    findings: Vec<Finding>,
+}
+
+pub async fn run(cmd: Args) -> anyhow::Result<(), anyhow::Error> {
+    if cmd.output == Format::Json {
+        output::status_to_stderr();
+    }
+
+    let root = cmd.root.unwrap_or_else(|| PathBuf::from("/"));
+    let Some(manifest) = Manifest::load(&root)? else {
+        print_error!(
+            "{} not found, was CasaOS installed by yacc?",
+            root.join(manifest::MANIFEST_FILE).display()
+        );
+    };
+    let findings: Vec<Finding> = manifest
+        .files
+        .iter()
+        .filter_map(|file| {
+            manifest::check(&root, file).map(|problem| Finding {
+                path: file.path.clone(),
+                component: file.component.clone(),
+                problem,
+                config: file.config,
+            })
+        })
+        .collect();
+    let failed = findings.iter().any(|f| !f.config);
+
+    match cmd.output {
+        Format::Json => print_output!(
+            "{}",
+            serde_json::to_string_pretty(&Report {
+                files: manifest.files.len(),
+                findings,
+            })?
+        ),
+        Format::Text => {
+            for f in findings.iter() {
+                let problem = match f.problem {
+                    Problem::Missing => "missing",
+                    Problem::Modified => "modified",
+                    Problem::Mode => "mode changed",
+                };
+                if f.config {
+                    print_info!("{} {} (config)", f.path.display(), style(problem).dim());
+                } else {
+                    print_warn!("{} {} ({})", f.path.display(), problem, f.component);
+                }
+            }
+            if failed {
+                print_warn!(
+                    "Some of the {} installed files differ.",
+                    manifest.files.len()
+                );
+            } else {
+                print_ok!("All {} installed files are intact.", manifest.files.len());
+            }
+        }
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -53,3 +53,20 @@ lazy_static::lazy_static! {
         "CasaOS-AppStore",
     ];
 }
+
+/// Minisign public keys trusted to sign CasaOS release tarballs.
+/// Extra keys for private mirrors can be passed with `--trusted-key`. While this is
+/// empty, installs only check checksums unless `--require-signatures` is passed.
+pub const TRUSTED_KEYS: &[&str] = &[];
+
+/// Default location of the yacc config file, overridden by `YACC_CONFIG`.
+pub const CONFIG_FILE: &str = "/etc/yacc/config.toml";
+
+/// Packages yacc installed as dependencies, so they can be told apart from the user's.
+pub const DEPENDENCIES_RECORD: &str = "/var/lib/yacc/dependencies.json";
+
+/// Oldest Docker Engine CasaOS works with, overridden by `--docker-min-version`.
+pub const DOCKER_MIN_VERSION: &str = "20.10.0";
+
+/// Where CasaOS keeps app data.
+pub const DATA_DIR: &str = "/DATA";
@@ -4,10 +4,13 @@
 macro_rules! print_error {
     ($($arg:tt)*) => {
         {
-            let console = console::Term::stdout();
+            let message = format!($($arg)*);
+            $crate::utils::oplog::message("error", &message);
+            let console = $crate::utils::output::status_term();
             console
-                .write_line(&format!("{} {}", console::style("[ ERROR ]").red(), format!($($arg)*)))
+                .write_line(&format!("{} {}", console::style("[ ERROR ]").red(), message))
                 .unwrap();
+            $crate::utils::oplog::finish(false);
             std::process::exit(1);
         }
     };
@@ -17,9 +20,11 @@ macro_rules! print_error {
 macro_rules! print_info {
     ($($arg:tt)*) => {
         {
-            let console = console::Term::stdout();
+            let message = format!($($arg)*);
+            $crate::utils::oplog::message("info", &message);
+            let console = $crate::utils::output::status_term();
             console
-                .write_line(&format!("{} {}", console::style("[ INFO ]").green(), format!($($arg)*)))
+                .write_line(&format!("{} {}", console::style("[ INFO ]").green(), message))
                 .unwrap();
         }
     };
@@ -29,33 +34,51 @@ macro_rules! print_info {
 macro_rules! print_warn {
     ($($arg:tt)*) => {
         {
-            let console = console::Term::stdout();
+            let message = format!($($arg)*);
+            $crate::utils::oplog::message("warn", &message);
+            let console = $crate::utils::output::status_term();
             console
-                .write_line(&format!("{} {}", console::style("[ WARN ]").yellow(), format!($($arg)*)))
+                .write_line(&format!("{} {}", console::style("[ WARN ]").yellow(), message))
                 .unwrap();
         }
     };
 }
 
 #[macro_export]
-macro_rules! print_output {
+macro_rules! print_fail {
     ($($arg:tt)*) => {
         {
-            let console = console::Term::stdout();
+            let message = format!($($arg)*);
+            $crate::utils::oplog::message("fail", &message);
+            let console = $crate::utils::output::status_term();
             console
-                .write_line(&format!("{}", format!($($arg)*)))
+                .write_line(&format!("{} {}", console::style("[ FAIL ]").red(), message))
                 .unwrap();
         }
     };
 }
 
 #[macro_export]
-macro_rules! print_ok {
+macro_rules! print_output {
     ($($arg:tt)*) => {
         {
+            let message = format!($($arg)*);
+            $crate::utils::oplog::message("output", &message);
             let console = console::Term::stdout();
+            console.write_line(&message).unwrap();
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! print_ok {
+    ($($arg:tt)*) => {
+        {
+            let message = format!($($arg)*);
+            $crate::utils::oplog::message("ok", &message);
+            let console = $crate::utils::output::status_term();
             console
-                .write_line(&format!("{} {}", console::style("[ OK ]").green(), format!($($arg)*)))
+                .write_line(&format!("{} {}", console::style("[ OK ]").green(), message))
                 .unwrap();
         }
     };
@@ -0,0 +1,119 @@
+use std::fmt;
+
+/// Architecture of the release packages, see the support matrix in the README.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arch {
+    Amd64,
+    Arm64,
+    #[clap(name = "arm-7")]
+    Arm7,
+}
+
+impl Arch {
+    /// Name used in release asset file names.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Arch::Amd64 => "amd64",
+            Arch::Arm64 => "arm64",
+            Arch::Arm7 => "arm-7",
+        }
+    }
+}
+
+impl fmt::Display for Arch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The CPU found at runtime and the packages it runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cpu {
+    /// e.g. `x86_64`, `aarch64`, `armv7` or `armv6`.
+    pub name: String,
+    /// `None` when no packages are published for it.
+    pub arch: Option<Arch>,
+}
+
+/// Detect the CPU. 32-bit ARM userlands are told apart by the variant of the CPU
+/// from `/proc/cpuinfo`, as ARMv6 boards can't run the `arm-7` packages.
+pub fn detect() -> Cpu {
+    let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").unwrap_or_default();
+    classify(std::env::consts::ARCH, &cpuinfo)
+}
+
+fn classify(target_arch: &str, cpuinfo: &str) -> Cpu {
+    let (name, arch) = match target_arch {
+        "x86_64" => ("x86_64".to_string(), Some(Arch::Amd64)),
+        "aarch64" => ("aarch64".to_string(), Some(Arch::Arm64)),
+        "arm" => match arm_variant(cpuinfo) {
+            Some(v) if v >= 7 => (format!("armv{}", v), Some(Arch::Arm7)),
+            Some(v) => (format!("armv{}", v), None),
+            // Without a cpuinfo, assume the common ARMv7.
+            None => ("arm".to_string(), Some(Arch::Arm7)),
+        },
+        other => (other.to_string(), None),
+    };
+    Cpu { name, arch }
+}
+
+/// ARM architecture version from `/proc/cpuinfo`. The `(v6l)` suffix of the model name
+/// is preferred, since ARMv6 kernels report `CPU architecture: 7` as well.
+pub fn arm_variant(cpuinfo: &str) -> Option<u8> {
+    let field = |key: &str| {
+        cpuinfo.lines().find_map(|line| {
+            let (k, v) = line.split_once(':')?;
+            (k.trim() == key).then(|| v.trim().to_string())
+        })
+    };
+    let from_model = field("model name").and_then(|model| {
+        let suffix = model.rsplit_once("(v")?.1;
+        suffix
+            .trim_end_matches(')')
+            .trim_end_matches(['l', 'b'])
+            .parse()
+            .ok()
+    });
+    from_model.or_else(|| {
+        field("CPU architecture")?
+            .trim_start_matches("AArch")
+            .trim_end_matches(char::is_alphabetic)
+            .parse()
+            .ok()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_arm_variant() {
+        let pi_zero = "processor\t: 0\nmodel name\t: ARMv6-compatible processor rev 7 (v6l)\nCPU architecture: 7\n";
+        assert_eq!(arm_variant(pi_zero), Some(6));
+        let pi_3 =
+            "processor\t: 0\nmodel name\t: ARMv7 Processor rev 4 (v7l)\nCPU architecture: 7\n";
+        assert_eq!(arm_variant(pi_3), Some(7));
+        let aarch64_kernel = "processor\t: 0\nBogoMIPS\t: 108.00\nCPU architecture: 8\n";
+        assert_eq!(arm_variant(aarch64_kernel), Some(8));
+        assert_eq!(arm_variant(""), None);
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify("x86_64", "").arch, Some(Arch::Amd64));
+        let pi_zero = "model name\t: ARMv6-compatible processor rev 7 (v6l)\n";
+        assert_eq!(
+            classify("arm", pi_zero),
+            Cpu {
+                name: "armv6".to_string(),
+                arch: None
+            }
+        );
+        assert_eq!(
+            classify("arm", "CPU architecture: 8\n").arch,
+            Some(Arch::Arm7)
+        );
+        assert_eq!(classify("riscv64", "").arch, None);
+    }
+}
@@ -0,0 +1,267 @@
+use crate::{
+    consts::CASA_PACKAGES,
+    utils::release::{self, Package},
+};
+use anyhow::Context;
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+use tar::Archive;
+
+/// Name of the manifest at the root of a bundle.
+pub const MANIFEST_FILE: &str = "bundle.json";
+/// Directory of the bundle holding the package tarballs.
+pub const PACKAGES_DIR: &str = "packages";
+/// Directory of the bundle holding `docker save` tarballs.
+pub const IMAGES_DIR: &str = "images";
+/// Name of the rclone archive entries in the manifest.
+pub const RCLONE: &str = "rclone";
+
+/// Describes the packages of an offline installation bundle.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Manifest {
+    pub version: u32,
+    pub packages: Vec<Entry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    pub name: String,
+    pub version: String,
+    /// Target architecture, `all` for architecture independent packages.
+    pub arch: String,
+    pub file_name: String,
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+/// Open a bundle given as a directory or a `.tar.gz`, which is unpacked into `work_dir`.
+/// Returns the directory holding the bundle contents.
+pub fn open(path: &Path, work_dir: &Path) -> anyhow::Result<PathBuf, anyhow::Error> {
+    if path.is_dir() {
+        return Ok(path.to_path_buf());
+    }
+
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    std::fs::create_dir_all(work_dir)?;
+    Archive::new(GzDecoder::new(file))
+        .unpack(work_dir)
+        .with_context(|| format!("Failed to extract bundle {}", path.display()))?;
+
+    // The archive may wrap everything in a single top level directory.
+    if work_dir.join(MANIFEST_FILE).exists() {
+        return Ok(work_dir.to_path_buf());
+    }
+    let mut entries = std::fs::read_dir(work_dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_dir());
+    match (entries.next(), entries.next()) {
+        (Some(dir), None) => Ok(dir),
+        _ => Ok(work_dir.to_path_buf()),
+    }
+}
+
+/// Packages of the bundle in `dir` that can be installed on `arch`.
+///
+/// Without a manifest every tarball named like a release asset is taken,
+/// looked up both in `packages/` and next to the manifest location.
+pub fn packages(dir: &Path, arch: &str) -> anyhow::Result<Vec<Package>, anyhow::Error> {
+    let entries = match manifest(dir)? {
+        Some(manifest) => manifest
+            .packages
+            .into_iter()
+            .filter(|e| e.arch == arch || e.arch == "all")
+            .collect::<Vec<_>>(),
+        None => scan(dir, arch)?,
+    };
+
+    let mut packages = vec![];
+    for package in CASA_PACKAGES.iter() {
+        let Some(entry) = entries.iter().find(|e| e.name == *package) else {
+            continue;
+        };
+        packages.push(locate(dir, entry)?);
+    }
+
+    if packages.is_empty() {
+        anyhow::bail!("No packages for {} found in bundle {}", arch, dir.display());
+    }
+    Ok(packages)
+}
+
+/// The rclone archive for `arch` of the bundle in `dir`, only found through the manifest.
+pub fn rclone(dir: &Path, arch: &str) -> anyhow::Result<Option<Package>, anyhow::Error> {
+    let Some(manifest) = manifest(dir)? else {
+        return Ok(None);
+    };
+    manifest
+        .packages
+        .iter()
+        .find(|e| e.name == RCLONE && e.arch == arch)
+        .map(|e| locate(dir, e))
+        .transpose()
+}
+
+fn manifest(dir: &Path) -> anyhow::Result<Option<Manifest>, anyhow::Error> {
+    let manifest_file = dir.join(MANIFEST_FILE);
+    if !manifest_file.exists() {
+        return Ok(None);
+    }
+    let manifest = serde_json::from_reader(File::open(&manifest_file)?)
+        .with_context(|| format!("Invalid bundle manifest {}", manifest_file.display()))?;
+    Ok(Some(manifest))
+}
+
+/// The package of a manifest entry and the path of its file.
+fn locate(dir: &Path, entry: &Entry) -> anyhow::Result<Package, anyhow::Error> {
+    let path = [dir.join(PACKAGES_DIR), dir.to_path_buf()]
+        .into_iter()
+        .map(|d| d.join(&entry.file_name))
+        .find(|p| p.exists())
+        .with_context(|| format!("{} is missing from the bundle", entry.file_name))?;
+
+    Ok(Package {
+        name: entry.name.clone(),
+        version: entry.version.clone(),
+        file_name: entry.file_name.clone(),
+        url: String::new(),
+        fallback_urls: vec![],
+        path: Some(path),
+        sha256: entry.sha256.clone(),
+    })
+}
+
+/// Docker image tarballs shipped in the bundle in `dir`.
+pub fn images(dir: &Path) -> Vec<PathBuf> {
+    let Ok(files) = std::fs::read_dir(dir.join(IMAGES_DIR)) else {
+        return vec![];
+    };
+    let mut images = files
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().unwrap_or_default() == "tar")
+        .collect::<Vec<_>>();
+    images.sort();
+    images
+}
+
+/// Find release tarballs for `arch` in a bundle without a manifest.
+fn scan(dir: &Path, arch: &str) -> anyhow::Result<Vec<Entry>, anyhow::Error> {
+    let mut entries = vec![];
+    for d in [dir.join(PACKAGES_DIR), dir.to_path_buf()] {
+        let Ok(files) = std::fs::read_dir(&d) else {
+            continue;
+        };
+        for file in files.filter_map(|e| e.ok()) {
+            let file_name = file.file_name().to_string_lossy().to_string();
+            for package in CASA_PACKAGES.iter() {
+                if let Some(tag) = release::asset_tag(package, arch, &file_name) {
+                    entries.push(Entry {
+                        name: package.to_string(),
+                        version: tag.trim_start_matches('v').to_string(),
+                        arch: arch.to_string(),
+                        file_name: file_name.clone(),
+                        sha256: None,
+                    });
+                }
+            }
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_packages_from_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let packages_dir = dir.path().join(PACKAGES_DIR);
+        std::fs::create_dir_all(&packages_dir).unwrap();
+        for file in [
+            "linux-amd64-casaos-v0.4.4.tar.gz",
+            "linux-arm64-casaos-v0.4.4.tar.gz",
+            "linux-all-casaos-v0.4.3.tar.gz",
+            "rclone-v1.65.2-linux-arm64.zip",
+        ] {
+            std::fs::write(packages_dir.join(file), b"").unwrap();
+        }
+        let entry = |name: &str, arch: &str, file_name: &str| Entry {
+            name: name.to_string(),
+            version: "0.4.4".to_string(),
+            arch: arch.to_string(),
+            file_name: file_name.to_string(),
+            sha256: Some("abc".to_string()),
+        };
+        let manifest = Manifest {
+            version: 1,
+            packages: vec![
+                entry("CasaOS", "amd64", "linux-amd64-casaos-v0.4.4.tar.gz"),
+                entry("CasaOS", "arm64", "linux-arm64-casaos-v0.4.4.tar.gz"),
+                entry("CasaOS-UI", "all", "linux-all-casaos-v0.4.3.tar.gz"),
+                entry(RCLONE, "arm64", "rclone-v1.65.2-linux-arm64.zip"),
+            ],
+        };
+        std::fs::write(
+            dir.path().join(MANIFEST_FILE),
+            serde_json::to_vec(&manifest).unwrap(),
+        )
+        .unwrap();
+
+        let packages = packages(dir.path(), "arm64").unwrap();
+        let names = packages
+            .iter()
+            .map(|p| p.file_name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "linux-arm64-casaos-v0.4.4.tar.gz",
+                "linux-all-casaos-v0.4.3.tar.gz"
+            ]
+        );
+        assert_eq!(packages[0].sha256.as_deref(), Some("abc"));
+
+        let rclone = rclone(dir.path(), "arm64").unwrap().unwrap();
+        assert_eq!(rclone.file_name, "rclone-v1.65.2-linux-arm64.zip");
+        assert!(super::rclone(dir.path(), "amd64").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_packages_without_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in [
+            "linux-amd64-casaos-v0.4.4.tar.gz",
+            "linux-amd64-casaos-gateway-v0.4.2.tar.gz",
+            "linux-all-casaos-v0.4.3.tar.gz",
+            "linux-arm64-casaos-v0.4.4.tar.gz",
+        ] {
+            std::fs::write(dir.path().join(file), b"").unwrap();
+        }
+
+        let packages = packages(dir.path(), "amd64").unwrap();
+        let found = packages
+            .iter()
+            .map(|p| (p.name.as_str(), p.version.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                ("CasaOS-Gateway", "0.4.2"),
+                ("CasaOS", "0.4.4"),
+                ("CasaOS-UI", "0.4.3")
+            ]
+        );
+        assert!(packages[0].url.is_empty());
+        assert_eq!(
+            packages[0].signature_path().unwrap(),
+            dir.path()
+                .join("linux-amd64-casaos-gateway-v0.4.2.tar.gz.minisig")
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use crate::utils::release::Package;
+use anyhow::Context;
+use reqwest::Client;
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, fs::File, io, path::Path};
+
+/// SHA-256 of a file as lowercase hex.
+pub fn sha256_file(path: &Path) -> anyhow::Result<String, anyhow::Error> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Parse a `sha256sum` style checksums file into file name -> digest.
+pub fn parse_checksums(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (digest, name) = line.trim().split_once(char::is_whitespace)?;
+            let name = name.trim_start().trim_start_matches('*');
+            Some((name.to_string(), digest.to_lowercase()))
+        })
+        .collect()
+}
+
+/// Expected digest of a package, taken from the release index when it carries one,
+/// otherwise from the `checksums.txt` published next to the tarball.
+pub async fn expected_digest(
+    client: &Client,
+    package: &Package,
+) -> anyhow::Result<Option<String>, anyhow::Error> {
+    if let Some(digest) = &package.sha256 {
+        return Ok(Some(digest.to_lowercase()));
+    }
+
+    let response = client.get(package.checksums_url()).send().await?;
+    if !response.status().is_success() {
+        return Ok(None);
+    }
+    let checksums = parse_checksums(&response.text().await?);
+    Ok(checksums.get(&package.file_name).cloned())
+}
+
+/// Verify a downloaded package against its expected digest.
+pub fn verify(path: &Path, package: &Package, expected: &str) -> anyhow::Result<(), anyhow::Error> {
+    let actual = sha256_file(path)?;
+    if !actual.eq_ignore_ascii_case(expected) {
+        anyhow::bail!(
+            "Checksum mismatch for {} ({}): expected {}, got {}",
+            package.name,
+            package.file_name,
+            expected,
+            actual
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::test_server::TestServer;
+
+    const TARBALL: &[u8] = b"not really a tarball";
+
+    fn package(server: &TestServer, sha256: Option<&str>) -> Package {
+        Package {
+            sha256: sha256.map(str::to_string),
+            ..Package::test(&server.url(""))
+        }
+    }
+
+    #[test]
+    fn test_parse_checksums() {
+        let checksums = parse_checksums("ABC123  a.tar.gz\ndef456 *b.tar.gz\n\n");
+        assert_eq!(checksums["a.tar.gz"], "abc123");
+        assert_eq!(checksums["b.tar.gz"], "def456");
+    }
+
+    #[tokio::test]
+    async fn test_verify_against_checksums_file() {
+        let digest = format!("{:x}", Sha256::digest(TARBALL));
+        let server = TestServer::start(vec![(
+            "/checksums.txt",
+            format!("{}  linux-amd64-casaos-v0.4.4.tar.gz\n", digest).into_bytes(),
+        )]);
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("linux-amd64-casaos-v0.4.4.tar.gz");
+        std::fs::write(&file, TARBALL).unwrap();
+
+        let package = package(&server, None);
+        let client = Client::new();
+        let expected = expected_digest(&client, &package).await.unwrap().unwrap();
+        assert!(verify(&file, &package, &expected).is_ok());
+
+        std::fs::write(&file, b"tampered").unwrap();
+        let err = verify(&file, &package, &expected).unwrap_err();
+        assert!(err.to_string().contains("Checksum mismatch for CasaOS"));
+    }
+
+    #[tokio::test]
+    async fn test_index_digest_wins() {
+        let server = TestServer::start(vec![]);
+        let client = Client::new();
+
+        let expected = expected_digest(&client, &package(&server, Some("ABC123")))
+            .await
+            .unwrap();
+        assert_eq!(expected.as_deref(), Some("abc123"));
+
+        let expected = expected_digest(&client, &package(&server, None))
+            .await
+            .unwrap();
+        assert_eq!(expected, None);
+    }
+}
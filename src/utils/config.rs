@@ -0,0 +1,68 @@
+use crate::consts::CONFIG_FILE;
+use anyhow::Context;
+use serde::Deserialize;
+use std::path::Path;
+
+/// Settings read from `/etc/yacc/config.toml`.
+/// Command line flags and environment variables take precedence.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Download mirrors, tried in order of latency.
+    #[serde(default)]
+    pub mirrors: Vec<String>,
+    /// Prefer the mirror for the region reported by ipapi.co.
+    #[serde(default)]
+    pub detect_region: bool,
+    /// Oldest acceptable Docker Engine version.
+    pub docker_min_version: Option<String>,
+    /// Mirror of download.docker.com to install Docker from.
+    pub docker_mirror: Option<String>,
+    /// SHA-256 of the only package scripts allowed to run, all when empty.
+    #[serde(default)]
+    pub allowed_scripts: Vec<String>,
+    /// SHA-256 of package scripts that must never run.
+    #[serde(default)]
+    pub denied_scripts: Vec<String>,
+}
+
+impl Config {
+    /// Load the config file, falling back to defaults when it does not exist.
+    pub fn load() -> anyhow::Result<Config, anyhow::Error> {
+        let path = std::env::var("YACC_CONFIG").unwrap_or_else(|_| CONFIG_FILE.to_string());
+        Config::load_from(Path::new(&path))
+    }
+
+    pub fn load_from(path: &Path) -> anyhow::Result<Config, anyhow::Error> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("Invalid config {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_load_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        assert!(Config::load_from(&path).unwrap().mirrors.is_empty());
+
+        std::fs::write(
+            &path,
+            "mirrors = [\"https://mirror.example.com/IceWhaleTech/\"]\ndetect_region = true\n",
+        )
+        .unwrap();
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.mirrors, ["https://mirror.example.com/IceWhaleTech/"]);
+        assert!(config.detect_region);
+
+        std::fs::write(&path, "mirror = \"typo\"\n").unwrap();
+        assert!(Config::load_from(&path).is_err());
+    }
+}
@@ -1,23 +1,198 @@
+use crate::print_info;
+use anyhow::Context;
 use console::Term;
 use dialoguer::{theme::ColorfulTheme, Confirm};
+use std::{
+    collections::HashMap,
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
 
-#[allow(dead_code)]
-pub fn confirm_default_yes(prompt: &str) -> anyhow::Result<bool> {
-    let confirmation = Confirm::with_theme(&ColorfulTheme::default())
-        .with_prompt(prompt)
-        .default(true)
-        .show_default(true)
-        .wait_for_newline(true)
-        .interact_on(&Term::stdout())?;
-    Ok(confirmation)
+/// Every prompt yacc asks, by the ID used in answer files.
+pub const PROMPTS: &[(&str, &str)] = &[
+    (
+        "preflight_continue",
+        "Continue installing when pre-flight checks warned",
+    ),
+    (
+        "install_docker",
+        "Install or upgrade Docker when it is missing or too old",
+    ),
+    (
+        "delete_containers",
+        "Delete all containers when uninstalling",
+    ),
+    (
+        "delete_images",
+        "Delete all images when uninstalling, otherwise only unused ones",
+    ),
+    ("delete_app_data", "Delete /DATA/AppData when uninstalling"),
+];
+
+/// Prompts that delete data, answered no by `--non-interactive` whatever their default.
+const DESTRUCTIVE: &[&str] = &["delete_containers", "delete_images", "delete_app_data"];
+
+/// How prompts are answered without asking, set once from the global flags.
+#[derive(clap::Args, Debug, Default, Clone)]
+#[clap(about = None, long_about = None)]
+pub struct Answers {
+    /// Answer yes to every prompt
+    #[clap(short, long, global = true)]
+    pub yes: bool,
+
+    /// Take the default answer of every prompt instead of asking, and no for the
+    /// ones deleting data
+    #[clap(long, global = true, conflicts_with = "yes")]
+    pub non_interactive: bool,
+
+    /// TOML or JSON file answering prompts by ID, e.g. `delete_containers = false`
+    #[clap(long, value_name = "PATH", env = "YACC_ANSWERS", global = true)]
+    pub answers: Option<PathBuf>,
+
+    #[clap(skip)]
+    file: HashMap<String, bool>,
+}
+
+static ANSWERS: Mutex<Option<Answers>> = Mutex::new(None);
+
+impl Answers {
+    /// Read the answer file, if any, and make the answers used by all prompts.
+    pub fn install(mut self) -> anyhow::Result<(), anyhow::Error> {
+        if let Some(path) = &self.answers {
+            self.file = load(path)?;
+        }
+        *ANSWERS.lock().unwrap() = Some(self);
+        Ok(())
+    }
+
+    /// Add answers of a profile, the answer file keeps precedence.
+    pub fn extend(answers: &HashMap<String, bool>) {
+        let mut current = ANSWERS.lock().unwrap();
+        let current = current.get_or_insert_with(Answers::default);
+        for (id, answer) in answers {
+            current.file.entry(id.clone()).or_insert(*answer);
+        }
+    }
+
+    /// The answer to prompt `id` without asking, `None` if it has to be asked.
+    /// Deleting data takes an explicit answer, `--non-interactive` alone answers no.
+    fn lookup(&self, id: &str, default: bool) -> Option<bool> {
+        if let Some(answer) = self.file.get(id) {
+            Some(*answer)
+        } else if self.yes {
+            Some(true)
+        } else if self.non_interactive {
+            Some(default && !DESTRUCTIVE.contains(&id))
+        } else {
+            None
+        }
+    }
+}
+
+/// Read an answer file, JSON for a `.json` extension and TOML otherwise.
+fn load(path: &Path) -> anyhow::Result<HashMap<String, bool>, anyhow::Error> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read answer file {}", path.display()))?;
+    let answers: HashMap<String, bool> = if path.extension().is_some_and(|e| e == "json") {
+        serde_json::from_str(&content)?
+    } else {
+        toml::from_str(&content)?
+    };
+    if let Some(id) = unknown_prompts(answers.keys()).first() {
+        anyhow::bail!(
+            "Unknown prompt `{}` in {}, expected one of: {}",
+            id,
+            path.display(),
+            PROMPTS
+                .iter()
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(answers)
+}
+
+/// The IDs that don't name a prompt.
+pub fn unknown_prompts<'a>(ids: impl IntoIterator<Item = &'a String>) -> Vec<&'a str> {
+    ids.into_iter()
+        .filter(|id| !PROMPTS.iter().any(|(known, _)| known == id))
+        .map(String::as_str)
+        .collect()
 }
 
-pub fn confirm_default_no(prompt: &str) -> anyhow::Result<bool> {
+/// Ask the yes/no prompt `id`, unless it was answered by the global flags or the answer file.
+/// Fails when it would have to ask without a terminal.
+pub fn confirm(id: &str, prompt: &str, default: bool) -> anyhow::Result<bool> {
+    debug_assert!(PROMPTS.iter().any(|(known, _)| *known == id));
+    let answer = ANSWERS
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|a| a.lookup(id, default));
+    if let Some(answer) = answer {
+        print_info!("{} {}", prompt, if answer { "yes" } else { "no" });
+        return Ok(answer);
+    }
+    if !std::io::stdin().is_terminal() || !Term::stdout().is_term() {
+        anyhow::bail!(
+            "`{}` needs an answer but there is no terminal, pass --yes, --non-interactive or an answer file with `{} = true|false`",
+            prompt,
+            id
+        );
+    }
     let confirmation = Confirm::with_theme(&ColorfulTheme::default())
         .with_prompt(prompt)
-        .default(false)
+        .default(default)
         .show_default(true)
         .wait_for_newline(true)
         .interact_on(&Term::stdout())?;
     Ok(confirmation)
 }
+
+pub fn confirm_default_yes(id: &str, prompt: &str) -> anyhow::Result<bool> {
+    confirm(id, prompt, true)
+}
+
+pub fn confirm_default_no(id: &str, prompt: &str) -> anyhow::Result<bool> {
+    confirm(id, prompt, false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lookup_order() {
+        let mut answers = Answers {
+            non_interactive: true,
+            ..Default::default()
+        };
+        answers.file.insert("delete_images".to_string(), false);
+        assert_eq!(answers.lookup("delete_images", true), Some(false));
+        assert_eq!(answers.lookup("delete_containers", false), Some(false));
+        assert_eq!(answers.lookup("delete_app_data", true), Some(false));
+        assert_eq!(answers.lookup("install_docker", true), Some(true));
+        answers.non_interactive = false;
+        answers.yes = true;
+        assert_eq!(answers.lookup("delete_containers", false), Some(true));
+        assert_eq!(Answers::default().lookup("install_docker", true), None);
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let toml = dir.path().join("answers.toml");
+        std::fs::write(&toml, "delete_containers = true\ndelete_images = false\n").unwrap();
+        let answers = load(&toml).unwrap();
+        assert_eq!(answers.get("delete_containers"), Some(&true));
+
+        let json = dir.path().join("answers.json");
+        std::fs::write(&json, r#"{"install_docker": true}"#).unwrap();
+        assert_eq!(load(&json).unwrap().get("install_docker"), Some(&true));
+
+        std::fs::write(&json, r#"{"delete_everything": true}"#).unwrap();
+        assert!(load(&json).is_err());
+    }
+}
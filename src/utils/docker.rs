@@ -0,0 +1,280 @@
+use crate::utils::{
+    oplog,
+    package::{Backend, Manager},
+    release::Version,
+};
+use anyhow::Context;
+use std::{
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    path::Path,
+    process::Command,
+    time::{Duration, Instant},
+};
+
+/// Socket the Docker daemon listens on.
+pub const SOCKET: &str = "/var/run/docker.sock";
+/// Upstream of the install script's package repositories.
+pub const DOWNLOAD_URL: &str = "https://download.docker.com";
+/// Mirror of `DOWNLOAD_URL` for mainland China.
+pub const ALIYUN_DOWNLOAD_URL: &str = "https://mirrors.aliyun.com/docker-ce";
+
+/// Sets up Docker's apt or dnf repository from `DOWNLOAD_URL` and installs from it.
+const INSTALL_SCRIPT: &str = include_str!("../../scripts/install-docker.sh");
+
+/// Where Docker is installed from when it is missing or too old.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// The distribution's own packages
+    Distro,
+    /// Docker's repositories, set up by a bundled install script
+    Script,
+}
+
+/// Distributions the install script knows Docker's repositories for, by `ID`.
+const SCRIPT_DISTROS: &[&str] = &[
+    "ubuntu",
+    "debian",
+    "raspbian",
+    "fedora",
+    "centos",
+    "rhel",
+    "rocky",
+    "almalinux",
+];
+
+impl Source {
+    /// Docker's repositories only serve some apt and dnf based distributions, and not
+    /// their derivatives. The others keep Docker recent enough in their own.
+    pub fn default_for(id: &str) -> Source {
+        match script_supports(id) {
+            true => Source::Script,
+            false => Source::Distro,
+        }
+    }
+}
+
+/// Whether the install script knows Docker's repositories for the distribution `id`.
+pub fn script_supports(id: &str) -> bool {
+    SCRIPT_DISTROS.contains(&id)
+}
+
+/// Versions of the Docker tools found on this machine.
+#[derive(Debug, Default)]
+pub struct Installation {
+    pub engine: Option<String>,
+    pub compose: Option<String>,
+}
+
+/// Find Docker Engine and the compose plugin.
+pub fn detect() -> Installation {
+    Installation {
+        engine: command_version("docker", &["--version"]),
+        compose: command_version("docker", &["compose", "version"]),
+    }
+}
+
+fn command_version(program: &str, args: &[&str]) -> Option<String> {
+    let output = oplog::capture(Command::new(program).args(args)).ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_version(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// The first dotted version number in the output of a `version` command, such as
+/// `24.0.5` in `Docker version 24.0.5, build ced0996`.
+pub fn parse_version(output: &str) -> Option<String> {
+    output.split_whitespace().find_map(|word| {
+        let word = word.trim_start_matches('v');
+        let end = word
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(word.len());
+        let version = word[..end].trim_end_matches('.');
+        (version.contains('.') && version.starts_with(|c: char| c.is_ascii_digit()))
+            .then(|| version.to_string())
+    })
+}
+
+/// Whether `version` is at least `minimum`.
+pub fn satisfies(version: &str, minimum: &str) -> bool {
+    Version::parse(version).core_cmp(&Version::parse(minimum)) >= std::cmp::Ordering::Equal
+}
+
+/// The package providing Docker Engine in a distribution's repositories, by its package
+/// manager, and the packages that may provide Compose v2, in order of preference.
+pub fn distro_packages(manager: Manager) -> (&'static str, &'static [&'static str]) {
+    match manager {
+        // Ubuntu calls the plugin `docker-compose-v2` and Docker's repository
+        // `docker-compose-plugin`. Debian's `docker-compose` is the old Python v1.
+        Manager::Apt => ("docker.io", &["docker-compose-v2", "docker-compose-plugin"]),
+        Manager::Dnf => ("moby-engine", &["docker-compose"]),
+        Manager::Pacman => ("docker", &["docker-compose"]),
+        Manager::Zypper => ("docker", &["docker-compose"]),
+        Manager::Apk => ("docker", &["docker-cli-compose"]),
+    }
+}
+
+/// The first of `candidates` the repositories of `backend` offer.
+pub fn first_available(
+    backend: &dyn Backend,
+    candidates: &[&'static str],
+) -> anyhow::Result<Option<&'static str>, anyhow::Error> {
+    for package in candidates {
+        if backend.is_available(package)? {
+            return Ok(Some(package));
+        }
+    }
+    Ok(None)
+}
+
+/// Install Docker from its repositories at `download_url` with the bundled script.
+pub fn install_with_script(download_url: &str) -> anyhow::Result<(), anyhow::Error> {
+    let status = oplog::run(
+        Command::new("sh")
+            .arg("-c")
+            .arg(INSTALL_SCRIPT)
+            .env("DOWNLOAD_URL", download_url.trim_end_matches('/')),
+    )
+    .context("Failed to run sh")?;
+    if !status.success() {
+        anyhow::bail!("The Docker install script failed, {}", status);
+    }
+    Ok(())
+}
+
+/// Ask the daemon behind `socket` whether it is up.
+pub fn ping(socket: &Path) -> anyhow::Result<(), anyhow::Error> {
+    let mut stream = UnixStream::connect(socket)
+        .with_context(|| format!("Failed to connect to {}", socket.display()))?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.write_all(b"GET /_ping HTTP/1.0\r\nHost: docker\r\n\r\n")?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let status = response.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        anyhow::bail!("Docker daemon answered `{}`", status);
+    }
+    Ok(())
+}
+
+/// Ping the daemon until it answers or `timeout` passes.
+pub fn wait_for_daemon(socket: &Path, timeout: Duration) -> anyhow::Result<(), anyhow::Error> {
+    let start = Instant::now();
+    loop {
+        match ping(socket) {
+            Ok(_) => return Ok(()),
+            Err(e) if start.elapsed() >= timeout => {
+                return Err(e.context("Docker daemon is not reachable"))
+            }
+            Err(_) => std::thread::sleep(Duration::from_millis(500)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            parse_version("Docker version 24.0.5, build ced0996").as_deref(),
+            Some("24.0.5")
+        );
+        assert_eq!(
+            parse_version("Docker version 20.10.24+dfsg1, build 297e128").as_deref(),
+            Some("20.10.24")
+        );
+        assert_eq!(
+            parse_version("Docker Compose version v2.20.2").as_deref(),
+            Some("2.20.2")
+        );
+        assert_eq!(parse_version("docker: command not found"), None);
+    }
+
+    #[test]
+    fn test_default_source() {
+        assert_eq!(Source::default_for("debian"), Source::Script);
+        assert_eq!(Source::default_for("rocky"), Source::Script);
+        assert_eq!(Source::default_for("linuxmint"), Source::Distro);
+        assert_eq!(Source::default_for("openEuler"), Source::Distro);
+        assert_eq!(Source::default_for("arch"), Source::Distro);
+    }
+
+    /// Repositories offering a fixed set of packages.
+    struct Repo(&'static [&'static str]);
+
+    impl Backend for Repo {
+        fn manager(&self) -> Manager {
+            Manager::Apt
+        }
+
+        fn is_installed(&self, _: &str) -> anyhow::Result<bool, anyhow::Error> {
+            Ok(false)
+        }
+
+        fn is_available(&self, package: &str) -> anyhow::Result<bool, anyhow::Error> {
+            Ok(self.0.contains(&package))
+        }
+
+        fn refresh(&mut self) -> anyhow::Result<(), anyhow::Error> {
+            Ok(())
+        }
+
+        fn install(&mut self, _: &[String]) -> anyhow::Result<(), anyhow::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_distro_packages() {
+        let (engine, compose) = distro_packages(Manager::Apt);
+        assert_eq!(engine, "docker.io");
+        assert!(!compose.contains(&"docker-compose"));
+
+        let ubuntu = Repo(&["docker.io", "docker-compose-v2"]);
+        let debian = Repo(&["docker.io", "docker-compose"]);
+        let docker = Repo(&["docker.io", "docker-compose-plugin"]);
+        assert_eq!(
+            first_available(&ubuntu, compose).unwrap(),
+            Some("docker-compose-v2")
+        );
+        assert_eq!(first_available(&debian, compose).unwrap(), None);
+        assert_eq!(
+            first_available(&docker, compose).unwrap(),
+            Some("docker-compose-plugin")
+        );
+    }
+
+    #[test]
+    fn test_satisfies() {
+        assert!(satisfies("24.0.5", "20.10"));
+        assert!(satisfies("20.10.0", "20.10"));
+        assert!(!satisfies("19.03.15", "20.10"));
+    }
+
+    #[test]
+    fn test_ping() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("docker.sock");
+        assert!(ping(&socket).is_err());
+
+        let listener = UnixListener::bind(&socket).unwrap();
+        std::thread::spawn(move || {
+            for (i, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request).unwrap();
+                let response = match i {
+                    0 => "HTTP/1.0 500 Internal Server Error\r\n\r\n",
+                    _ => "HTTP/1.0 200 OK\r\nContent-Length: 2\r\n\r\nOK",
+                };
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        assert!(ping(&socket).is_err());
+        wait_for_daemon(&socket, Duration::from_secs(5)).unwrap();
+    }
+}
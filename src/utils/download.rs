@@ -0,0 +1,413 @@
+use anyhow::Context;
+use indicatif::ProgressBar;
+use reqwest::{
+    header::{CONTENT_LENGTH, CONTENT_RANGE, RANGE},
+    Client, StatusCode,
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt, sync::Semaphore};
+
+/// Longest wait between two attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Longest wait for the next chunk of a response before the attempt is given up.
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A status the mirror will keep answering, so retrying it is pointless.
+#[derive(Debug)]
+struct Permanent(StatusCode);
+
+impl std::fmt::Display for Permanent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HTTP {}", self.0)
+    }
+}
+
+impl std::error::Error for Permanent {}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Number of files downloaded at the same time.
+    pub parallel: usize,
+    /// Attempts per mirror after the first one failed.
+    pub retries: u32,
+    /// Wait before the first retry, doubled for every further one.
+    pub backoff: Duration,
+    /// Bytes per second shared by all downloads.
+    pub limit_rate: Option<u64>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            parallel: 4,
+            retries: 3,
+            backoff: Duration::from_secs(1),
+            limit_rate: None,
+        }
+    }
+}
+
+/// A file to download, from the first url that works.
+pub struct Job {
+    pub urls: Vec<String>,
+    pub dest: PathBuf,
+    pub progress: ProgressBar,
+}
+
+/// Download all jobs, at most `options.parallel` at a time.
+/// Returns for every job the url that served it, or why it failed.
+pub async fn download_all(
+    client: &Client,
+    jobs: Vec<Job>,
+    options: &Options,
+) -> Vec<anyhow::Result<String, anyhow::Error>> {
+    let semaphore = Semaphore::new(options.parallel.max(1));
+    let limiter = options.limit_rate.map(RateLimiter::new);
+
+    let downloads = jobs.iter().map(|job| async {
+        let _permit = semaphore.acquire().await?;
+        let result = download(client, job, options, limiter.as_ref()).await;
+        match &result {
+            Ok(_) => job.progress.finish_with_message("Downloaded"),
+            Err(_) => job.progress.abandon_with_message("Failed"),
+        }
+        result
+    });
+    futures::future::join_all(downloads).await
+}
+
+/// Try every url of a job with retries, resuming whatever is already on disk.
+/// A client error moves on to the next url right away.
+async fn download(
+    client: &Client,
+    job: &Job,
+    options: &Options,
+    limiter: Option<&RateLimiter>,
+) -> anyhow::Result<String, anyhow::Error> {
+    let mut last_error = anyhow::anyhow!("No url to download {} from", job.dest.display());
+    for url in job.urls.iter() {
+        for attempt in 0..=options.retries {
+            if attempt > 0 {
+                let backoff = options.backoff.saturating_mul(1 << (attempt - 1).min(16));
+                tokio::time::sleep(backoff.min(MAX_BACKOFF)).await;
+            }
+            match fetch(client, url, &job.dest, &job.progress, limiter).await {
+                Ok(_) => return Ok(url.clone()),
+                Err(e) => {
+                    let permanent = e.is::<Permanent>();
+                    job.progress.set_message(format!("{:#}, retrying", e));
+                    last_error = e.context(format!("Failed to download {}", url));
+                    if permanent {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    Err(last_error)
+}
+
+/// A single attempt. Resumes with a `Range` request when part of the file exists
+/// and only appends when the server answers with exactly the requested range.
+async fn fetch(
+    client: &Client,
+    url: &str,
+    dest: &Path,
+    progress: &ProgressBar,
+    limiter: Option<&RateLimiter>,
+) -> anyhow::Result<(), anyhow::Error> {
+    let have = std::fs::metadata(dest).map(|m| m.len()).unwrap_or(0);
+    let mut request = client.get(url);
+    if have > 0 {
+        request = request.header(RANGE, format!("bytes={}-", have));
+    }
+    let response = request.send().await?;
+
+    let (append, total) = match response.status() {
+        StatusCode::PARTIAL_CONTENT => {
+            let (start, total) = response
+                .headers()
+                .get(CONTENT_RANGE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_content_range)
+                .context("Invalid Content-Range in partial response")?;
+            if start != Some(have) {
+                std::fs::remove_file(dest)?;
+                anyhow::bail!("Server resumed at {:?} instead of byte {}", start, have);
+            }
+            (true, total)
+        }
+        StatusCode::RANGE_NOT_SATISFIABLE => {
+            let total = response
+                .headers()
+                .get(CONTENT_RANGE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_content_range)
+                .and_then(|(_, total)| total);
+            if total == Some(have) {
+                progress.set_length(have);
+                progress.set_position(have);
+                return Ok(());
+            }
+            std::fs::remove_file(dest)?;
+            anyhow::bail!("Local file does not match the remote one, starting over");
+        }
+        status if status.is_success() => {
+            let total = response
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse().ok());
+            (false, total)
+        }
+        status
+            if status.is_client_error()
+                && status != StatusCode::REQUEST_TIMEOUT
+                && status != StatusCode::TOO_MANY_REQUESTS =>
+        {
+            return Err(Permanent(status).into())
+        }
+        status => anyhow::bail!("HTTP {}", status),
+    };
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(dest)
+        .await?;
+    let mut written = if append { have } else { 0 };
+    if let Some(total) = total {
+        progress.set_length(total);
+    }
+    progress.set_position(written);
+
+    let mut response = response;
+    while let Some(chunk) = tokio::time::timeout(READ_TIMEOUT, response.chunk())
+        .await
+        .map_err(|_| anyhow::anyhow!("No data received for {:?}", READ_TIMEOUT))??
+    {
+        if let Some(limiter) = limiter {
+            limiter.consume(chunk.len() as u64).await;
+        }
+        file.write_all(&chunk).await?;
+        written += chunk.len() as u64;
+        progress.set_position(written);
+    }
+    file.flush().await?;
+
+    match total {
+        Some(total) if written != total => {
+            anyhow::bail!("Incomplete download, got {} of {} bytes", written, total)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Parse `bytes 100-199/200` or `bytes */200` into the start offset and total size.
+fn parse_content_range(value: &str) -> Option<(Option<u64>, Option<u64>)> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let total = total.parse().ok();
+    let start = match range {
+        "*" => None,
+        range => Some(range.split_once('-')?.0.parse().ok()?),
+    };
+    Some((start, total))
+}
+
+/// Parse a rate such as `500K`, `2M` or `1048576` into bytes per second.
+pub fn parse_rate(s: &str) -> anyhow::Result<u64, anyhow::Error> {
+    let s = s.trim();
+    let (number, unit) = match s.char_indices().find(|(_, c)| c.is_ascii_alphabetic()) {
+        Some((i, _)) => s.split_at(i),
+        None => (s, ""),
+    };
+    let multiplier = match unit.to_ascii_uppercase().trim_end_matches("/S") {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        _ => anyhow::bail!("unknown unit `{}` in rate `{}`", unit, s),
+    };
+    let number: f64 = number
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid rate `{}`", s))?;
+    if number <= 0.0 {
+        anyhow::bail!("rate must be positive");
+    }
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Keeps the combined throughput of all downloads below a rate.
+struct RateLimiter {
+    rate: u64,
+    state: Mutex<(Instant, u64)>,
+}
+
+impl RateLimiter {
+    fn new(rate: u64) -> RateLimiter {
+        RateLimiter {
+            rate: rate.max(1),
+            state: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Account for `bytes` and wait until they fit into the rate.
+    async fn consume(&self, bytes: u64) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            state.1 += bytes;
+            let due = Duration::from_secs_f64(state.1 as f64 / self.rate as f64);
+            due.saturating_sub(state.0.elapsed())
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::test_server::{Fault, TestServer};
+
+    fn body() -> Vec<u8> {
+        (0..64 * 1024).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn options() -> Options {
+        Options {
+            backoff: Duration::from_millis(10),
+            ..Default::default()
+        }
+    }
+
+    fn job(urls: Vec<String>, dest: &Path) -> Job {
+        Job {
+            urls,
+            dest: dest.to_path_buf(),
+            progress: ProgressBar::hidden(),
+        }
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("1024").unwrap(), 1024);
+        assert_eq!(parse_rate("500K").unwrap(), 500 * 1024);
+        assert_eq!(parse_rate("1.5m").unwrap(), 1536 * 1024);
+        assert_eq!(parse_rate("2MB/s").unwrap(), 2 * 1024 * 1024);
+        assert!(parse_rate("fast").is_err());
+        assert!(parse_rate("0").is_err());
+    }
+
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(
+            parse_content_range("bytes 100-199/200"),
+            Some((Some(100), Some(200)))
+        );
+        assert_eq!(parse_content_range("bytes */200"), Some((None, Some(200))));
+        assert_eq!(parse_content_range("bytes 0-9/*"), Some((Some(0), None)));
+    }
+
+    #[tokio::test]
+    async fn test_retries_and_resumes() {
+        let server = TestServer::start_with_faults(
+            vec![("/file", body())],
+            vec![("/file", Fault::Status(503)), ("/file", Fault::Cut)],
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("file");
+
+        let results = download_all(
+            &Client::new(),
+            vec![job(vec![server.url("/file")], &dest)],
+            &options(),
+        )
+        .await;
+        assert_eq!(results[0].as_ref().unwrap(), &server.url("/file"));
+        assert_eq!(std::fs::read(&dest).unwrap(), body());
+        assert_eq!(server.hits("/file"), 3);
+    }
+
+    #[tokio::test]
+    async fn test_resume_without_duplicating_bytes() {
+        let server = TestServer::start(vec![("/file", body())]);
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("file");
+        std::fs::write(&dest, &body()[..1000]).unwrap();
+
+        let results = download_all(
+            &Client::new(),
+            vec![job(vec![server.url("/file")], &dest)],
+            &options(),
+        )
+        .await;
+        assert!(results[0].is_ok());
+        assert_eq!(std::fs::read(&dest).unwrap(), body());
+
+        // A complete file is answered with 416 and left alone.
+        let results = download_all(
+            &Client::new(),
+            vec![job(vec![server.url("/file")], &dest)],
+            &options(),
+        )
+        .await;
+        assert!(results[0].is_ok());
+        assert_eq!(std::fs::read(&dest).unwrap(), body());
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_next_mirror_and_reports_errors() {
+        let server = TestServer::start(vec![("/good", body())]);
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            retries: 1,
+            ..options()
+        };
+
+        let results = download_all(
+            &Client::new(),
+            vec![
+                job(
+                    vec![server.url("/missing"), server.url("/good")],
+                    &dir.path().join("a"),
+                ),
+                job(vec![server.url("/gone")], &dir.path().join("b")),
+            ],
+            &options,
+        )
+        .await;
+        assert_eq!(results[0].as_ref().unwrap(), &server.url("/good"));
+        assert_eq!(server.hits("/missing"), 1);
+        assert_eq!(server.hits("/gone"), 1);
+        let err = results[1].as_ref().unwrap_err();
+        assert!(format!("{:#}", err).contains("404"));
+    }
+
+    #[tokio::test]
+    async fn test_limit_rate() {
+        let server = TestServer::start(vec![("/file", body())]);
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            limit_rate: Some(256 * 1024),
+            ..options()
+        };
+
+        let start = Instant::now();
+        let results = download_all(
+            &Client::new(),
+            vec![job(vec![server.url("/file")], &dir.path().join("file"))],
+            &options,
+        )
+        .await;
+        assert!(results[0].is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+}
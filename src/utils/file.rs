@@ -1,3 +1,4 @@
+#[allow(dead_code)]
 pub fn replace_string_in_file(file_path: &str, old_str: &str, new_str: &str) {
     let file_content = std::fs::read_to_string(file_path).expect("Failed to read file");
 
@@ -0,0 +1,115 @@
+use anyhow::Context;
+use ini::Ini;
+use std::path::Path;
+
+/// Gateway config, relative to the root.
+pub const GATEWAY_INI: &str = "etc/casaos/gateway.ini";
+/// Port the gateway listens on when its config does not set one.
+pub const DEFAULT_PORT: u16 = 80;
+pub const SERVICE: &str = "casaos-gateway.service";
+
+/// Port set in the gateway config at `path`, `None` if it has none.
+pub fn port(path: &Path) -> Option<u16> {
+    let config = Ini::load_from_file(path).ok()?;
+    config
+        .section(Some("gateway"))?
+        .get("port")?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Set the port of the gateway config at `path`, creating it if needed.
+/// Only the port line changes, so edits and comments in the rest of it are kept.
+pub fn set_port(path: &Path, port: u16) -> anyhow::Result<(), anyhow::Error> {
+    let content = match path.exists() {
+        true => std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?,
+        false => String::new(),
+    };
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, with_port(&content, port))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// `content` with the `port` of its `[gateway]` section set to `port`.
+fn with_port(content: &str, port: u16) -> String {
+    let line = format!("port={}", port);
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    let mut section = String::new();
+    let mut header = None;
+    for (i, l) in lines.iter_mut().enumerate() {
+        let trimmed = l.trim();
+        if let Some(name) = trimmed.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+            section = name.trim().to_string();
+            if section == "gateway" {
+                header = Some(i);
+            }
+            continue;
+        }
+        let key = trimmed.split_once('=').map(|(k, _)| k.trim());
+        if section == "gateway" && key.is_some_and(|k| k.eq_ignore_ascii_case("port")) {
+            *l = line;
+            return lines.join("\n") + "\n";
+        }
+    }
+    match header {
+        Some(i) => lines.insert(i + 1, line),
+        None => {
+            if lines.last().is_some_and(|l| !l.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push("[gateway]".to_string());
+            lines.push(line);
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// The first port after `port` not in `listening`, wrapping around to 1024.
+pub fn free_port(port: u16, listening: &[u16]) -> Option<u16> {
+    (port.saturating_add(1)..=u16::MAX)
+        .chain(1024..port)
+        .find(|p| !listening.contains(p))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_set_port() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(GATEWAY_INI);
+        assert_eq!(port(&path), None);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(
+            &path,
+            "[common]\nruntimepath=/var/run/casaos\n\n[gateway]\nport=80\n",
+        )
+        .unwrap();
+
+        set_port(&path, 8080).unwrap();
+        assert_eq!(port(&path), Some(8080));
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.contains("runtimepath=/var/run/casaos"));
+
+        assert_eq!(
+            with_port("; mine\n[gateway]\nlogpath=/var/log\n", 81),
+            "; mine\n[gateway]\nport=81\nlogpath=/var/log\n"
+        );
+        assert_eq!(
+            with_port("[common]\nport=1\n", 81),
+            "[common]\nport=1\n\n[gateway]\nport=81\n"
+        );
+        assert_eq!(with_port("", 81), "[gateway]\nport=81\n");
+    }
+
+    #[test]
+    fn test_free_port() {
+        assert_eq!(free_port(80, &[80, 81, 82]), Some(83));
+        assert_eq!(free_port(u16::MAX, &[u16::MAX, 1024]), Some(1025));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::utils::checksum;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+/// Where the manifest is kept, relative to the root.
+pub const MANIFEST_FILE: &str = "var/lib/casaos/manifest.json";
+/// Format version written by this yacc.
+pub const VERSION: u32 = 1;
+
+/// Every file an install put in place, so it can be verified and removed again.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Manifest {
+    pub version: u32,
+    /// Installed version of every component, by package name.
+    #[serde(default)]
+    pub components: BTreeMap<String, String>,
+    pub files: Vec<FileEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileEntry {
+    /// Installed path, absolute within the root.
+    pub path: PathBuf,
+    /// Package the file came from.
+    pub component: String,
+    pub version: String,
+    /// Digest of the packaged file, `None` for symlinks.
+    pub sha256: Option<String>,
+    /// Permission bits, e.g. `0o755`.
+    pub mode: u32,
+    /// Config files the user may have edited, kept on upgrades and reported apart by verify.
+    pub config: bool,
+}
+
+/// Which package provided each file, by path relative to the sysroot.
+pub type Owners = HashMap<PathBuf, (String, String)>;
+
+impl Manifest {
+    /// Read the manifest under `root`, `None` when CasaOS was installed without one.
+    pub fn load(root: &Path) -> anyhow::Result<Option<Manifest>, anyhow::Error> {
+        let path = root.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content =
+            std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let manifest: Manifest = serde_json::from_slice(&content)
+            .with_context(|| format!("Invalid manifest {}", path.display()))?;
+        if manifest.version > VERSION {
+            anyhow::bail!(
+                "Manifest {} has version {}, this yacc only reads up to {}",
+                path.display(),
+                manifest.version,
+                VERSION
+            );
+        }
+        Ok(Some(manifest))
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&FileEntry> {
+        self.files.iter().find(|f| f.path == path)
+    }
+
+    /// Installed version of every component. Manifests written before versions were
+    /// recorded apart fall back to the files, where kept configs may be older.
+    pub fn components(&self) -> Vec<(String, String)> {
+        if !self.components.is_empty() {
+            return self
+                .components
+                .iter()
+                .map(|(name, version)| (name.clone(), version.clone()))
+                .collect();
+        }
+        let mut components: Vec<(String, String)> = vec![];
+        for file in self.files.iter().filter(|f| !f.config) {
+            if !components.iter().any(|(name, _)| *name == file.component) {
+                components.push((file.component.clone(), file.version.clone()));
+            }
+        }
+        components
+    }
+}
+
+impl FileEntry {
+    /// Describe the file at `src` that is installed as `path`.
+    pub fn new(
+        src: &Path,
+        path: &Path,
+        component: &str,
+        version: &str,
+    ) -> anyhow::Result<FileEntry, anyhow::Error> {
+        let meta = std::fs::symlink_metadata(src)?;
+        let sha256 = match meta.file_type().is_symlink() {
+            true => None,
+            false => Some(checksum::sha256_file(src)?),
+        };
+        Ok(FileEntry {
+            path: path.to_path_buf(),
+            component: component.to_string(),
+            version: version.to_string(),
+            sha256,
+            mode: meta.permissions().mode() & 0o7777,
+            config: path.starts_with("/etc"),
+        })
+    }
+}
+
+/// How an installed file differs from its manifest entry.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Problem {
+    Missing,
+    Modified,
+    Mode,
+}
+
+/// Compare the file installed under `root` with its entry.
+pub fn check(root: &Path, entry: &FileEntry) -> Option<Problem> {
+    let path = root.join(entry.path.strip_prefix("/").unwrap_or(&entry.path));
+    let Ok(meta) = std::fs::symlink_metadata(&path) else {
+        return Some(Problem::Missing);
+    };
+    if let Some(expected) = &entry.sha256 {
+        if !checksum::sha256_file(&path).is_ok_and(|actual| actual == *expected) {
+            return Some(Problem::Modified);
+        }
+        if meta.permissions().mode() & 0o7777 != entry.mode {
+            return Some(Problem::Mode);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("usr/bin")).unwrap();
+        std::fs::write(root.join("usr/bin/casaos"), "binary").unwrap();
+        std::fs::set_permissions(
+            root.join("usr/bin/casaos"),
+            std::fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+
+        let entry = FileEntry::new(
+            &root.join("usr/bin/casaos"),
+            Path::new("/usr/bin/casaos"),
+            "CasaOS",
+            "0.4.4",
+        )
+        .unwrap();
+        assert_eq!(entry.mode, 0o755);
+        assert!(!entry.config);
+        assert_eq!(check(root, &entry), None);
+
+        std::fs::set_permissions(
+            root.join("usr/bin/casaos"),
+            std::fs::Permissions::from_mode(0o777),
+        )
+        .unwrap();
+        assert_eq!(check(root, &entry), Some(Problem::Mode));
+        std::fs::write(root.join("usr/bin/casaos"), "changed").unwrap();
+        assert_eq!(check(root, &entry), Some(Problem::Modified));
+        std::fs::remove_file(root.join("usr/bin/casaos")).unwrap();
+        assert_eq!(check(root, &entry), Some(Problem::Missing));
+    }
+
+    #[test]
+    fn test_components_with_kept_config() {
+        let file = |path: &str, version: &str| FileEntry {
+            path: PathBuf::from(path),
+            component: "CasaOS-Gateway".to_string(),
+            version: version.to_string(),
+            sha256: Some(String::new()),
+            mode: 0o644,
+            config: path.starts_with("/etc"),
+        };
+        let mut manifest = Manifest {
+            version: VERSION,
+            components: BTreeMap::new(),
+            files: vec![
+                file("/etc/casaos/gateway.ini", "0.4.2"),
+                file("/usr/bin/casaos-gateway", "0.4.4"),
+            ],
+        };
+        let expected = [("CasaOS-Gateway".to_string(), "0.4.4".to_string())];
+        assert_eq!(manifest.components(), expected);
+
+        manifest.files.pop();
+        manifest
+            .components
+            .insert("CasaOS-Gateway".to_string(), "0.4.4".to_string());
+        assert_eq!(manifest.components(), expected);
+    }
+
+    #[test]
+    fn test_load_rejects_newer_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Manifest::load(dir.path()).unwrap().is_none());
+        let path = dir.path().join(MANIFEST_FILE);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, r#"{"version": 2, "files": []}"#).unwrap();
+        assert!(Manifest::load(dir.path()).is_err());
+    }
+}
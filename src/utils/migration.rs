@@ -0,0 +1,219 @@
+use crate::{
+    print_info,
+    utils::{checksum, oplog, script},
+};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use walkdir::WalkDir;
+
+/// Migrations that ran, relative to the root.
+pub const STATE_FILE: &str = "var/lib/casaos/migrations.json";
+/// Output of every migration script, relative to the root.
+pub const LOG_DIR: &str = "var/log/yacc/migration";
+
+/// A migration that completed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Applied {
+    /// File name of the script.
+    pub name: String,
+    pub sha256: String,
+    pub from_version: String,
+    pub to_version: String,
+    /// Seconds since the epoch.
+    pub applied_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct State {
+    pub applied: Vec<Applied>,
+}
+
+impl State {
+    pub fn load(path: &Path) -> anyhow::Result<State, anyhow::Error> {
+        match std::fs::read(path) {
+            Ok(content) => serde_json::from_slice(&content)
+                .with_context(|| format!("Invalid migration state {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(State::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<(), anyhow::Error> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// A script counts as applied when one with the same name and content ran before.
+    pub fn is_applied(&self, name: &str, sha256: &str) -> bool {
+        self.applied
+            .iter()
+            .any(|a| a.name == name && a.sha256 == sha256)
+    }
+}
+
+/// Runs the migration scripts of the packages being installed.
+#[derive(Debug)]
+pub struct Runner {
+    pub root: PathBuf,
+    /// Installed CasaOS version, empty on a first install.
+    pub from_version: String,
+    pub to_version: String,
+    pub timeout: Duration,
+    /// Print each script before running it.
+    pub show: bool,
+}
+
+impl Runner {
+    /// Run the scripts in `dir` that were not applied yet, in order, stopping at the first failure.
+    pub fn run(&self, dir: &Path) -> anyhow::Result<(), anyhow::Error> {
+        let state_file = self.root.join(STATE_FILE);
+        let log_dir = self.root.join(LOG_DIR);
+        std::fs::create_dir_all(&log_dir)?;
+        let mut state = State::load(&state_file)?;
+
+        for path in scripts(dir) {
+            let name = path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            let sha256 = checksum::sha256_file(&path)?;
+            if state.is_applied(&name, &sha256) {
+                print_info!("Migration {} was already applied, skipping.", name);
+                continue;
+            }
+
+            if self.show {
+                script::show(&path)?;
+            }
+            print_info!("Running migration {}...", console::style(&name).bold());
+            let log = log_dir.join(format!("{}-{}.log", now(), name));
+            let log_file = File::create(&log)
+                .with_context(|| format!("Failed to create {}", log.display()))?;
+            let envs = [
+                ("FROM_VERSION", self.from_version.as_str()),
+                ("TO_VERSION", self.to_version.as_str()),
+            ];
+            let status = script::run(&path, &envs, &log_file, self.timeout)
+                .with_context(|| format!("Migration {} failed, see {}", name, log.display()))?;
+            oplog::output(&name, Some(status), &script::tail(&log, 0, usize::MAX));
+            if !status.success() {
+                anyhow::bail!(
+                    "Migration {} failed, {}, see {}",
+                    name,
+                    status,
+                    log.display()
+                );
+            }
+            state.applied.push(Applied {
+                name,
+                sha256,
+                from_version: self.from_version.clone(),
+                to_version: self.to_version.clone(),
+                applied_at: now(),
+            });
+            state.save(&state_file)?;
+        }
+        Ok(())
+    }
+}
+
+/// Migration scripts in `dir`, ordered by the number their file name starts with,
+/// so `9-a.sh` runs before `10-b.sh`, then by name.
+pub fn scripts(dir: &Path) -> Vec<PathBuf> {
+    let mut scripts: Vec<PathBuf> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| e.into_path())
+        .filter(|p| p.is_file() && p.extension().unwrap_or_default() == "sh")
+        .collect();
+    scripts.sort_by_cached_key(|p| {
+        let name = p
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let digits: String = name.chars().take_while(char::is_ascii_digit).collect();
+        (digits.parse::<u64>().unwrap_or(u64::MAX), name, p.clone())
+    });
+    scripts
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_script(dir: &Path, name: &str, content: &str) {
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(dir.join(name), content).unwrap();
+    }
+
+    #[test]
+    fn test_scripts_order() {
+        let dir = tempfile::tempdir().unwrap();
+        write_script(&dir.path().join("b"), "9-gateway.sh", "");
+        write_script(&dir.path().join("a"), "10-casaos.sh", "");
+        write_script(dir.path(), "cleanup.sh", "");
+        write_script(dir.path(), "README.md", "");
+        let names: Vec<String> = scripts(dir.path())
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, ["9-gateway.sh", "10-casaos.sh", "cleanup.sh"]);
+    }
+
+    #[test]
+    fn test_runner() {
+        let root = tempfile::tempdir().unwrap();
+        let scripts = root.path().join("script.d");
+        write_script(
+            &scripts,
+            "10-env.sh",
+            "echo \"$FROM_VERSION -> $TO_VERSION\" >> \"$(dirname \"$0\")/../ran\"\n",
+        );
+        let runner = Runner {
+            root: root.path().to_path_buf(),
+            from_version: "0.4.3".to_string(),
+            to_version: "0.4.4".to_string(),
+            timeout: Duration::from_secs(10),
+            show: false,
+        };
+        runner.run(&scripts).unwrap();
+        runner.run(&scripts).unwrap();
+        let ran = std::fs::read_to_string(root.path().join("ran")).unwrap();
+        assert_eq!(ran, "0.4.3 -> 0.4.4\n");
+        let state = State::load(&root.path().join(STATE_FILE)).unwrap();
+        assert_eq!(state.applied.len(), 1);
+        assert_eq!(state.applied[0].to_version, "0.4.4");
+
+        write_script(&scripts, "20-slow.sh", "echo started; sleep 10\n");
+        let runner = Runner {
+            timeout: Duration::from_millis(300),
+            ..runner
+        };
+        let error = format!("{:#}", runner.run(&scripts).unwrap_err());
+        assert!(error.contains("timed out"));
+        assert_eq!(
+            State::load(&root.path().join(STATE_FILE))
+                .unwrap()
+                .applied
+                .len(),
+            1
+        );
+    }
+}
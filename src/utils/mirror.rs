@@ -0,0 +1,99 @@
+use reqwest::Client;
+use std::time::{Duration, Instant};
+
+pub const GITHUB: &str = "https://github.com/IceWhaleTech/";
+pub const ALIYUN: &str = "https://casaos.oss-cn-shanghai.aliyuncs.com/IceWhaleTech/";
+
+/// Mirrors used when none are configured.
+pub const DEFAULT_MIRRORS: &[&str] = &[GITHUB, ALIYUN];
+
+/// How long a mirror may take to answer the latency probe.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct Mirror {
+    pub url: String,
+    /// Round trip of the probe, `None` when the mirror did not answer.
+    pub latency: Option<Duration>,
+}
+
+/// Mirror urls always end with a slash so paths can be appended.
+pub fn normalize(url: &str) -> String {
+    let url = url.trim();
+    if url.ends_with('/') {
+        url.to_string()
+    } else {
+        format!("{}/", url)
+    }
+}
+
+/// Probe every mirror and order them by latency, unreachable mirrors last.
+/// The order of the input breaks ties, so unreachable mirrors keep their given order.
+pub async fn rank(client: &Client, mirrors: &[String]) -> Vec<Mirror> {
+    let probes = mirrors.iter().map(|url| async move {
+        Mirror {
+            url: url.clone(),
+            latency: probe(client, url).await,
+        }
+    });
+    let mut ranked = futures::future::join_all(probes).await;
+    ranked.sort_by_key(|m| m.latency.unwrap_or(Duration::MAX));
+    ranked
+}
+
+/// Time a `HEAD` request to the mirror. Any HTTP answer counts as reachable.
+async fn probe(client: &Client, url: &str) -> Option<Duration> {
+    let start = Instant::now();
+    match client.head(url).timeout(PROBE_TIMEOUT).send().await {
+        Ok(_) => Some(start.elapsed()),
+        Err(_) => None,
+    }
+}
+
+/// Country code of this machine as reported by ipapi.co, `None` when it can't be told.
+pub async fn detect_region(client: &Client) -> Option<String> {
+    let response = client
+        .get("https://ipapi.co/json")
+        .timeout(PROBE_TIMEOUT)
+        .send()
+        .await
+        .ok()?;
+    let json = response.json::<serde_json::Value>().await.ok()?;
+    json["country_code"].as_str().map(str::to_string)
+}
+
+/// Mirror that serves a region best, if any.
+pub fn preferred_for_region(country_code: &str) -> Option<&'static str> {
+    match country_code {
+        "CN" => Some(ALIYUN),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::test_server::TestServer;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("https://example.com/a"), "https://example.com/a/");
+        assert_eq!(
+            normalize(" https://example.com/a/ "),
+            "https://example.com/a/"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rank_puts_unreachable_last() {
+        let server = TestServer::start(vec![]);
+        let unreachable = "http://127.0.0.1:9/".to_string();
+        let reachable = server.url("/");
+
+        let ranked = rank(&Client::new(), &[unreachable.clone(), reachable.clone()]).await;
+        assert_eq!(ranked[0].url, reachable);
+        assert!(ranked[0].latency.is_some());
+        assert_eq!(ranked[1].url, unreachable);
+        assert!(ranked[1].latency.is_none());
+    }
+}
@@ -1,3 +1,28 @@
+pub mod arch;
+pub mod bundle;
+pub mod checksum;
+pub mod config;
 pub mod confirm;
+pub mod docker;
+pub mod download;
 pub mod file;
+pub mod gateway;
+pub mod manifest;
+pub mod migration;
+pub mod mirror;
+pub mod network;
+pub mod oplog;
+pub mod output;
+pub mod package;
+pub mod plan;
+pub mod preflight;
+pub mod profile;
+pub mod rclone;
+pub mod readiness;
+pub mod release;
+pub mod script;
+pub mod signature;
 pub mod systemd;
+#[cfg(test)]
+pub mod test_server;
+pub mod transaction;
@@ -0,0 +1,168 @@
+use crate::utils::gateway;
+use serde::Serialize;
+use std::{
+    ffi::CStr,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::Path,
+};
+
+/// Name prefixes of interfaces that never carry the address CasaOS is reached at.
+const VIRTUAL_PREFIXES: &[&str] = &["docker", "veth", "br-", "virbr", "cni", "flannel"];
+
+/// A physical interface and the addresses CasaOS can be reached at through it.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Interface {
+    pub name: String,
+    pub ipv4: Vec<Ipv4Addr>,
+    pub ipv6: Vec<Ipv6Addr>,
+    /// Web UI address for every IP, IPv4 first.
+    pub urls: Vec<String>,
+}
+
+/// Interfaces that are up and not virtual, with the URLs of a gateway on `port`.
+pub fn interfaces(port: u16) -> std::io::Result<Vec<Interface>> {
+    Ok(group(addresses()?, Path::new("/sys"), port))
+}
+
+/// Every address of the interfaces that are up, as `(interface, address)`.
+fn addresses() -> std::io::Result<Vec<(String, IpAddr)>> {
+    let mut head: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: getifaddrs only writes the head of a list it allocated into `head`.
+    if unsafe { libc::getifaddrs(&mut head) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let mut addresses = vec![];
+    let mut cursor = head;
+    // SAFETY: every node of the list stays valid until `freeifaddrs` below.
+    while let Some(ifa) = unsafe { cursor.as_ref() } {
+        cursor = ifa.ifa_next;
+        let up = ifa.ifa_flags & libc::IFF_UP as u32 != 0;
+        let loopback = ifa.ifa_flags & libc::IFF_LOOPBACK as u32 != 0;
+        if !up || loopback || ifa.ifa_addr.is_null() {
+            continue;
+        }
+        // SAFETY: `ifa_name` is a NUL-terminated string owned by the list.
+        let name = unsafe { CStr::from_ptr(ifa.ifa_name) }
+            .to_string_lossy()
+            .to_string();
+        // SAFETY: `ifa_addr` is not null, and its family tells which sockaddr it points to.
+        let address = unsafe {
+            match (*ifa.ifa_addr).sa_family as i32 {
+                libc::AF_INET => {
+                    let sin = &*(ifa.ifa_addr as *const libc::sockaddr_in);
+                    IpAddr::V4(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)))
+                }
+                libc::AF_INET6 => {
+                    let sin6 = &*(ifa.ifa_addr as *const libc::sockaddr_in6);
+                    IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr))
+                }
+                _ => continue,
+            }
+        };
+        addresses.push((name, address));
+    }
+    // SAFETY: `head` came from getifaddrs and nothing points into the list anymore.
+    unsafe { libc::freeifaddrs(head) };
+    Ok(addresses)
+}
+
+/// Whether `name` is a bridge, veth or other software interface, by its prefix or by
+/// being listed under `<sys>/devices/virtual/net`.
+fn is_virtual(name: &str, sys: &Path) -> bool {
+    VIRTUAL_PREFIXES.iter().any(|p| name.starts_with(p))
+        || sys.join("devices/virtual/net").join(name).exists()
+}
+
+/// Whether a browser can reach `ip` from another machine.
+fn is_reachable(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => !ip.is_loopback() && !ip.is_unspecified() && !ip.is_link_local(),
+        // Link-local addresses would need the zone in the URL.
+        IpAddr::V6(ip) => {
+            !ip.is_loopback() && !ip.is_unspecified() && (ip.segments()[0] & 0xffc0) != 0xfe80
+        }
+    }
+}
+
+/// Web UI address of a gateway on `ip` and `port`.
+pub fn url(ip: &IpAddr, port: u16) -> String {
+    let host = match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("[{}]", ip),
+    };
+    match port {
+        gateway::DEFAULT_PORT => format!("http://{}", host),
+        port => format!("http://{}:{}", host, port),
+    }
+}
+
+fn group(addresses: Vec<(String, IpAddr)>, sys: &Path, port: u16) -> Vec<Interface> {
+    let mut interfaces: Vec<Interface> = vec![];
+    for (name, ip) in addresses {
+        if is_virtual(&name, sys) || !is_reachable(&ip) {
+            continue;
+        }
+        let i = match interfaces.iter().position(|i| i.name == name) {
+            Some(i) => i,
+            None => {
+                interfaces.push(Interface {
+                    name,
+                    ipv4: vec![],
+                    ipv6: vec![],
+                    urls: vec![],
+                });
+                interfaces.len() - 1
+            }
+        };
+        match ip {
+            IpAddr::V4(ip) => interfaces[i].ipv4.push(ip),
+            IpAddr::V6(ip) => interfaces[i].ipv6.push(ip),
+        }
+    }
+    for interface in interfaces.iter_mut() {
+        interface.urls = interface
+            .ipv4
+            .iter()
+            .map(|ip| IpAddr::V4(*ip))
+            .chain(interface.ipv6.iter().map(|ip| IpAddr::V6(*ip)))
+            .map(|ip| url(&ip, port))
+            .collect();
+    }
+    interfaces
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_group() {
+        let sys = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(sys.path().join("devices/virtual/net/tailscale0")).unwrap();
+        let addresses = vec![
+            ("eth0".to_string(), "192.168.1.10".parse().unwrap()),
+            ("eth0".to_string(), "fe80::1".parse().unwrap()),
+            ("eth0".to_string(), "2001:db8::10".parse().unwrap()),
+            ("docker0".to_string(), "172.17.0.1".parse().unwrap()),
+            ("veth1234".to_string(), "172.17.0.2".parse().unwrap()),
+            ("tailscale0".to_string(), "100.64.0.1".parse().unwrap()),
+            ("wlan0".to_string(), "10.0.0.5".parse().unwrap()),
+        ];
+        let interfaces = group(addresses, sys.path(), 8080);
+        assert_eq!(interfaces.len(), 2);
+        assert_eq!(interfaces[0].name, "eth0");
+        assert_eq!(
+            interfaces[0].urls,
+            ["http://192.168.1.10:8080", "http://[2001:db8::10]:8080"]
+        );
+        assert_eq!(interfaces[1].ipv4, [Ipv4Addr::new(10, 0, 0, 5)]);
+    }
+
+    #[test]
+    fn test_url() {
+        let ip: IpAddr = "192.168.1.10".parse().unwrap();
+        assert_eq!(url(&ip, 80), "http://192.168.1.10");
+        let ip: IpAddr = "2001:db8::10".parse().unwrap();
+        assert_eq!(url(&ip, 81), "http://[2001:db8::10]:81");
+    }
+}
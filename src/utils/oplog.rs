@@ -0,0 +1,356 @@
+use crate::utils::script;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Output, Stdio},
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// What happened during an operation, one JSON object per line of its log.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Event {
+    Start {
+        operation: String,
+        args: Vec<String>,
+        version: String,
+    },
+    Message {
+        level: String,
+        message: String,
+    },
+    Step {
+        name: String,
+    },
+    StepDone {
+        name: String,
+        elapsed_ms: u64,
+    },
+    Output {
+        command: String,
+        status: Option<i32>,
+        output: String,
+    },
+    End {
+        success: bool,
+        elapsed_ms: u64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Record {
+    /// Milliseconds since the epoch.
+    pub ts: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// The log of the running operation.
+struct Oplog {
+    file: File,
+    started: Instant,
+    step: Option<(String, Instant)>,
+}
+
+impl Oplog {
+    fn write(&mut self, event: Event) {
+        let record = Record {
+            ts: now_ms(),
+            event,
+        };
+        if let Ok(line) = serde_json::to_string(&record) {
+            let _ = writeln!(self.file, "{}", line);
+        }
+    }
+
+    fn end_step(&mut self) {
+        if let Some((name, started)) = self.step.take() {
+            let elapsed_ms = started.elapsed().as_millis() as u64;
+            self.write(Event::StepDone { name, elapsed_ms });
+        }
+    }
+}
+
+static OPLOG: Mutex<Option<Oplog>> = Mutex::new(None);
+
+/// Directory of the operation logs, `YACC_LOG_DIR` or `/var/log/yacc`.
+pub fn log_dir() -> PathBuf {
+    std::env::var_os("YACC_LOG_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new("/").join(script::LOG_DIR))
+}
+
+/// Start logging `operation`, returning its ID.
+pub fn start(operation: &str) -> anyhow::Result<String, anyhow::Error> {
+    let dir = log_dir();
+    std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let stamp = timestamp(now_ms() / 1000)
+        .replace(['-', ':'], "")
+        .replace(' ', "-");
+    let mut id = format!("{}-{}", stamp, operation);
+    let mut n = 1;
+    while dir.join(format!("{}.jsonl", id)).exists() {
+        n += 1;
+        id = format!("{}-{}-{}", stamp, operation, n);
+    }
+    let path = dir.join(format!("{}.jsonl", id));
+    let file =
+        File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+
+    let mut oplog = Oplog {
+        file,
+        started: Instant::now(),
+        step: None,
+    };
+    oplog.write(Event::Start {
+        operation: operation.to_string(),
+        args: std::env::args().skip(1).collect(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    });
+    *OPLOG.lock().unwrap() = Some(oplog);
+    Ok(id)
+}
+
+fn with<F: FnOnce(&mut Oplog)>(f: F) {
+    // Logging stops quietly when the lock was poisoned by a panic.
+    if let Ok(mut oplog) = OPLOG.lock() {
+        if let Some(oplog) = oplog.as_mut() {
+            f(oplog);
+        }
+    }
+}
+
+/// Record a message printed by the `print_*` macros.
+pub fn message(level: &str, message: &str) {
+    with(|oplog| {
+        oplog.write(Event::Message {
+            level: level.to_string(),
+            message: console::strip_ansi_codes(message).to_string(),
+        })
+    });
+}
+
+/// Begin the step `name`, ending the previous one.
+pub fn step(name: &str) {
+    with(|oplog| {
+        oplog.end_step();
+        oplog.write(Event::Step {
+            name: name.to_string(),
+        });
+        oplog.step = Some((name.to_string(), Instant::now()));
+    });
+}
+
+/// Record what a subprocess printed.
+pub fn output(command: &str, status: Option<ExitStatus>, output: &str) {
+    with(|oplog| {
+        oplog.write(Event::Output {
+            command: command.to_string(),
+            status: status.and_then(|s| s.code()),
+            output: output.to_string(),
+        })
+    });
+}
+
+/// Run `command` in front of the user, recording what it prints as well.
+pub fn run(command: &mut Command) -> std::io::Result<ExitStatus> {
+    let (status, text) = run_tee(command)?;
+    output(&program(command), Some(status), &text);
+    Ok(status)
+}
+
+/// Run `command` with its output passed on to the terminal, returning it too.
+fn run_tee(command: &mut Command) -> std::io::Result<(ExitStatus, String)> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let captured = Arc::new(Mutex::new(vec![]));
+    let stdout = child
+        .stdout
+        .take()
+        .map(|out| tee(out, std::io::stdout(), &captured));
+    let stderr = child
+        .stderr
+        .take()
+        .map(|err| tee(err, std::io::stderr(), &captured));
+    let status = child.wait()?;
+    for handle in stdout.into_iter().chain(stderr) {
+        let _ = handle.join();
+    }
+    let captured = captured.lock().unwrap_or_else(|e| e.into_inner());
+    Ok((status, String::from_utf8_lossy(&captured).to_string()))
+}
+
+/// Run `command` quietly for the caller to read its output, recording it too.
+pub fn capture(command: &mut Command) -> std::io::Result<Output> {
+    let result = command.output()?;
+    let mut text = String::from_utf8_lossy(&result.stdout).to_string();
+    text.push_str(&String::from_utf8_lossy(&result.stderr));
+    output(&program(command), Some(result.status), &text);
+    Ok(result)
+}
+
+/// Copy `from` to `to` line by line as it comes, keeping a copy in `captured`.
+fn tee<R, W>(from: R, mut to: W, captured: &Arc<Mutex<Vec<u8>>>) -> JoinHandle<()>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let captured = captured.clone();
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(from);
+        let mut line = vec![];
+        while matches!(reader.read_until(b'\n', &mut line), Ok(n) if n > 0) {
+            let _ = to.write_all(&line);
+            let _ = to.flush();
+            if let Ok(mut captured) = captured.lock() {
+                captured.extend_from_slice(&line);
+            }
+            line.clear();
+        }
+    })
+}
+
+fn program(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|a| a.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// End the running operation.
+pub fn finish(success: bool) {
+    if let Ok(mut oplog) = OPLOG.lock() {
+        if let Some(mut oplog) = oplog.take() {
+            oplog.end_step();
+            let elapsed_ms = oplog.started.elapsed().as_millis() as u64;
+            oplog.write(Event::End {
+                success,
+                elapsed_ms,
+            });
+        }
+    }
+}
+
+/// Read the records of the operation `id`.
+pub fn read(id: &str) -> anyhow::Result<Vec<Record>, anyhow::Error> {
+    let path = log_dir().join(format!("{}.jsonl", id));
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("No operation {} in {}", id, log_dir().display()))?;
+    Ok(parse(&content))
+}
+
+/// Records of a log, skipping lines cut off by a crash.
+pub fn parse(content: &str) -> Vec<Record> {
+    content
+        .lines()
+        .filter_map(|l| serde_json::from_str(l).ok())
+        .collect()
+}
+
+/// IDs of the logged operations, oldest first.
+pub fn list() -> anyhow::Result<Vec<String>, anyhow::Error> {
+    let dir = log_dir();
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut ids: Vec<String> = std::fs::read_dir(&dir)?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            e.file_name()
+                .to_string_lossy()
+                .strip_suffix(".jsonl")
+                .map(str::to_string)
+        })
+        .collect();
+    ids.sort();
+    Ok(ids)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// `YYYY-MM-DD HH:MM:SS` in UTC.
+pub fn timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    // Civil date from days since the epoch, after Howard Hinnant's algorithm.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(timestamp(951782400), "2000-02-29 00:00:00");
+        assert_eq!(timestamp(1792324981), "2026-10-18 12:03:01");
+    }
+
+    #[test]
+    fn test_run_tee() {
+        let (status, text) =
+            run_tee(Command::new("sh").args(["-c", "echo out; sleep 0.1; echo err >&2; exit 2"]))
+                .unwrap();
+        assert_eq!(status.code(), Some(2));
+        assert_eq!(text, "out\nerr\n");
+        let output = capture(Command::new("sh").args(["-c", "echo out"])).unwrap();
+        assert_eq!(output.stdout, b"out\n");
+    }
+
+    #[test]
+    fn test_parse() {
+        let records = vec![
+            Record {
+                ts: 1,
+                event: Event::Step {
+                    name: "Download".to_string(),
+                },
+            },
+            Record {
+                ts: 2,
+                event: Event::End {
+                    success: true,
+                    elapsed_ms: 1,
+                },
+            },
+        ];
+        let mut content: String = records
+            .iter()
+            .map(|r| serde_json::to_string(r).unwrap() + "\n")
+            .collect();
+        assert!(content.contains(r#"{"ts":1,"kind":"step","name":"Download"}"#));
+        content.push_str("{\"ts\":3,\"kind\":\"mess");
+        assert_eq!(parse(&content), records);
+    }
+}
@@ -0,0 +1,25 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// How a command reports its result.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+static STATUS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Send status messages to stderr so stdout only carries machine readable output.
+pub fn status_to_stderr() {
+    STATUS_TO_STDERR.store(true, Ordering::Relaxed);
+}
+
+/// Terminal the `print_*` status macros write to.
+pub fn status_term() -> console::Term {
+    if STATUS_TO_STDERR.load(Ordering::Relaxed) {
+        console::Term::stderr()
+    } else {
+        console::Term::stdout()
+    }
+}
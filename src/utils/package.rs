@@ -0,0 +1,361 @@
+use crate::utils::oplog;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::{path::Path, process::Command};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Manager {
+    Apt,
+    Dnf,
+    Pacman,
+    Zypper,
+    Apk,
+}
+
+impl Manager {
+    /// Package manager of a distribution, from the `ID` and `ID_LIKE` of its os-release.
+    pub fn detect(id: &str, id_like: &str) -> Option<Manager> {
+        std::iter::once(id)
+            .chain(id_like.split_whitespace())
+            .find_map(|id| match id {
+                "debian" | "ubuntu" | "raspbian" => Some(Manager::Apt),
+                "fedora" | "rhel" | "centos" | "rocky" | "almalinux" => Some(Manager::Dnf),
+                "arch" | "manjaro" | "endeavouros" => Some(Manager::Pacman),
+                "opensuse" | "opensuse-leap" | "opensuse-tumbleweed" | "suse" | "sles" => {
+                    Some(Manager::Zypper)
+                }
+                "alpine" => Some(Manager::Apk),
+                _ => None,
+            })
+    }
+
+    /// Name of a dependency in this manager's repositories, `None` when it isn't packaged.
+    pub fn package_name<'a>(&self, dependency: &'a str) -> Option<&'a str> {
+        match (dependency, self) {
+            ("udevil", Manager::Apt) => Some("udevil"),
+            ("udevil", _) => None,
+            // Fedora ships SELinux instead.
+            ("apparmor", Manager::Dnf) => None,
+            ("apparmor", Manager::Zypper) => Some("apparmor-parser"),
+            (name, _) => Some(name),
+        }
+    }
+}
+
+/// Queries and installs packages, so the logic can be tested without a real system.
+pub trait Backend {
+    fn manager(&self) -> Manager;
+    fn is_installed(&self, package: &str) -> anyhow::Result<bool, anyhow::Error>;
+    fn is_available(&self, package: &str) -> anyhow::Result<bool, anyhow::Error>;
+    fn refresh(&mut self) -> anyhow::Result<(), anyhow::Error>;
+    fn install(&mut self, packages: &[String]) -> anyhow::Result<(), anyhow::Error>;
+}
+
+/// The package manager of the running system.
+pub struct System {
+    manager: Manager,
+}
+
+impl System {
+    pub fn new(manager: Manager) -> System {
+        System { manager }
+    }
+}
+
+/// Run a query quietly, telling only whether it succeeded.
+fn query(program: &str, args: &[&str]) -> anyhow::Result<bool, anyhow::Error> {
+    let output = oplog::capture(Command::new(program).args(args))
+        .with_context(|| format!("Failed to run {}", program))?;
+    Ok(output.status.success())
+}
+
+/// Run a command in front of the user, failing when it does.
+fn run(program: &str, args: &[&str]) -> anyhow::Result<(), anyhow::Error> {
+    let status = oplog::run(
+        Command::new(program)
+            .args(args)
+            .env("DEBIAN_FRONTEND", "noninteractive"),
+    )
+    .with_context(|| format!("Failed to run {}", program))?;
+    if !status.success() {
+        anyhow::bail!("`{} {}` failed, {}", program, args.join(" "), status);
+    }
+    Ok(())
+}
+
+/// Whether a dpkg `${Status}` such as `install ok installed` means the package is installed.
+/// A missing package reads `unknown ok not-installed`.
+fn dpkg_installed(status: &str) -> bool {
+    status.split_whitespace().nth(2) == Some("installed")
+}
+
+impl Backend for System {
+    fn manager(&self) -> Manager {
+        self.manager
+    }
+
+    fn is_installed(&self, package: &str) -> anyhow::Result<bool, anyhow::Error> {
+        match self.manager {
+            Manager::Apt => {
+                let output = oplog::capture(Command::new("dpkg-query").args([
+                    "-W",
+                    "-f=${Status}",
+                    package,
+                ]))?;
+                Ok(dpkg_installed(&String::from_utf8_lossy(&output.stdout)))
+            }
+            Manager::Dnf | Manager::Zypper => query("rpm", &["-q", package]),
+            Manager::Pacman => query("pacman", &["-Q", package]),
+            Manager::Apk => query("apk", &["info", "-e", package]),
+        }
+    }
+
+    fn is_available(&self, package: &str) -> anyhow::Result<bool, anyhow::Error> {
+        match self.manager {
+            Manager::Apt => {
+                let output = oplog::capture(Command::new("apt-cache").args(["show", package]))?;
+                Ok(output.status.success() && !output.stdout.is_empty())
+            }
+            Manager::Dnf => query("dnf", &["-q", "info", package]),
+            Manager::Pacman => query("pacman", &["-Si", package]),
+            Manager::Zypper => query(
+                "zypper",
+                &["--non-interactive", "search", "--match-exact", package],
+            ),
+            Manager::Apk => {
+                let output = oplog::capture(Command::new("apk").args(["search", "-e", package]))?;
+                Ok(!output.stdout.is_empty())
+            }
+        }
+    }
+
+    fn refresh(&mut self) -> anyhow::Result<(), anyhow::Error> {
+        match self.manager {
+            Manager::Apt => run("apt-get", &["update"]),
+            Manager::Dnf => run("dnf", &["makecache"]),
+            // Arch does not support partial upgrades, `install` syncs and upgrades instead.
+            Manager::Pacman => Ok(()),
+            Manager::Zypper => run("zypper", &["--non-interactive", "refresh"]),
+            Manager::Apk => run("apk", &["update"]),
+        }
+    }
+
+    fn install(&mut self, packages: &[String]) -> anyhow::Result<(), anyhow::Error> {
+        let packages = packages.iter().map(String::as_str);
+        let (program, args): (&str, Vec<&str>) = match self.manager {
+            Manager::Apt => ("apt-get", vec!["install", "-y"]),
+            Manager::Dnf => ("dnf", vec!["install", "-y"]),
+            Manager::Pacman => ("pacman", vec!["-Syu", "--noconfirm", "--needed"]),
+            Manager::Zypper => ("zypper", vec!["--non-interactive", "install"]),
+            Manager::Apk => ("apk", vec!["add"]),
+        };
+        run(
+            program,
+            &args.into_iter().chain(packages).collect::<Vec<_>>(),
+        )
+    }
+}
+
+/// What `ensure` found and did.
+#[derive(Debug, Default, PartialEq)]
+pub struct Outcome {
+    /// Packages that were installed now.
+    pub installed: Vec<String>,
+    /// Packages that were installed already.
+    pub present: Vec<String>,
+    /// Packages the repositories don't have.
+    pub unavailable: Vec<String>,
+    /// Dependencies that are not packaged for this distribution.
+    pub unsupported: Vec<String>,
+}
+
+/// Install the packages for `dependencies` that are missing.
+/// Packages the repositories don't offer are reported instead of failing the install.
+pub fn ensure(
+    backend: &mut dyn Backend,
+    dependencies: &[&str],
+) -> anyhow::Result<Outcome, anyhow::Error> {
+    let manager = backend.manager();
+    let mut outcome = Outcome::default();
+    let mut missing = vec![];
+    for dependency in dependencies {
+        let Some(package) = manager.package_name(dependency) else {
+            outcome.unsupported.push(dependency.to_string());
+            continue;
+        };
+        if backend.is_installed(package)? {
+            outcome.present.push(package.to_string());
+        } else {
+            missing.push(package.to_string());
+        }
+    }
+    if missing.is_empty() {
+        return Ok(outcome);
+    }
+
+    backend.refresh()?;
+    for package in missing {
+        if backend.is_available(&package)? {
+            outcome.installed.push(package);
+        } else {
+            outcome.unavailable.push(package);
+        }
+    }
+    if !outcome.installed.is_empty() {
+        backend.install(&outcome.installed)?;
+    }
+    Ok(outcome)
+}
+
+/// Packages yacc installed, so they can be told apart from the user's own.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Record {
+    pub manager: Manager,
+    pub packages: Vec<String>,
+}
+
+/// Add `packages` to the record at `path`.
+pub fn record(
+    path: &Path,
+    manager: Manager,
+    packages: &[String],
+) -> anyhow::Result<(), anyhow::Error> {
+    let mut record = match std::fs::read(path) {
+        Ok(content) => serde_json::from_slice(&content)
+            .with_context(|| format!("Invalid record {}", path.display()))?,
+        Err(_) => Record {
+            manager,
+            packages: vec![],
+        },
+    };
+    for package in packages {
+        if !record.packages.contains(package) {
+            record.packages.push(package.clone());
+        }
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(&record)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Fake {
+        manager: Manager,
+        installed: Vec<String>,
+        available: Vec<String>,
+        refreshed: bool,
+    }
+
+    impl Backend for Fake {
+        fn manager(&self) -> Manager {
+            self.manager
+        }
+
+        fn is_installed(&self, package: &str) -> anyhow::Result<bool, anyhow::Error> {
+            Ok(self.installed.iter().any(|p| p == package))
+        }
+
+        fn is_available(&self, package: &str) -> anyhow::Result<bool, anyhow::Error> {
+            assert!(self.refreshed, "queried before refreshing");
+            Ok(self.available.iter().any(|p| p == package))
+        }
+
+        fn refresh(&mut self) -> anyhow::Result<(), anyhow::Error> {
+            self.refreshed = true;
+            Ok(())
+        }
+
+        fn install(&mut self, packages: &[String]) -> anyhow::Result<(), anyhow::Error> {
+            self.installed.extend_from_slice(packages);
+            Ok(())
+        }
+    }
+
+    fn fake(manager: Manager, installed: &[&str], available: &[&str]) -> Fake {
+        Fake {
+            manager,
+            installed: installed.iter().map(|p| p.to_string()).collect(),
+            available: available.iter().map(|p| p.to_string()).collect(),
+            refreshed: false,
+        }
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Manager::detect("debian", ""), Some(Manager::Apt));
+        assert_eq!(
+            Manager::detect("linuxmint", "ubuntu debian"),
+            Some(Manager::Apt)
+        );
+        assert_eq!(
+            Manager::detect("rocky", "rhel centos fedora"),
+            Some(Manager::Dnf)
+        );
+        assert_eq!(
+            Manager::detect("opensuse-leap", "suse opensuse"),
+            Some(Manager::Zypper)
+        );
+        assert_eq!(Manager::detect("nixos", ""), None);
+    }
+
+    #[test]
+    fn test_ensure_installs_only_missing() {
+        let mut backend = fake(
+            Manager::Zypper,
+            &["curl"],
+            &["curl", "samba", "apparmor-parser"],
+        );
+        let outcome = ensure(
+            &mut backend,
+            &["curl", "samba", "udevil", "apparmor", "mergerfs"],
+        )
+        .unwrap();
+        assert_eq!(
+            outcome,
+            Outcome {
+                installed: vec!["samba".to_string(), "apparmor-parser".to_string()],
+                present: vec!["curl".to_string()],
+                unavailable: vec!["mergerfs".to_string()],
+                unsupported: vec!["udevil".to_string()],
+            }
+        );
+        assert!(backend.is_installed("samba").unwrap());
+    }
+
+    #[test]
+    fn test_ensure_without_missing_does_nothing() {
+        let mut backend = fake(Manager::Apt, &["curl", "wget"], &[]);
+        let outcome = ensure(&mut backend, &["curl", "wget"]).unwrap();
+        assert!(outcome.installed.is_empty());
+        assert!(!backend.refreshed);
+    }
+
+    #[test]
+    fn test_dpkg_installed() {
+        assert!(dpkg_installed("install ok installed"));
+        assert!(!dpkg_installed("unknown ok not-installed"));
+        assert!(!dpkg_installed("deinstall ok config-files"));
+        assert!(!dpkg_installed(""));
+    }
+
+    #[test]
+    fn test_record_merges() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state/dependencies.json");
+        record(&path, Manager::Apt, &["samba".to_string()]).unwrap();
+        record(
+            &path,
+            Manager::Apt,
+            &["samba".to_string(), "udevil".to_string()],
+        )
+        .unwrap();
+        let record: Record = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(record.packages, ["samba", "udevil"]);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::{
+    print_output,
+    utils::{migration, release::Package},
+};
+use console::style;
+use indicatif::HumanBytes;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Directory of a package build holding the migration scripts.
+pub const MIGRATION_SCRIPT_DIR: &str = "scripts/migration/script.d";
+/// Directory of a package build holding the setup scripts.
+pub const SETUP_SCRIPT_DIR: &str = "scripts/setup/script.d";
+/// Directory of a package build mirroring the files to install.
+pub const SYSROOT_DIR: &str = "sysroot";
+
+/// Everything an install would do, as shown by `install --dry-run`.
+#[derive(Serialize, Debug)]
+pub struct Plan {
+    /// Mirror or bundle the packages come from.
+    pub source: String,
+    pub packages: Vec<PlannedPackage>,
+    pub stop_services: Vec<String>,
+    pub migration_scripts: Vec<String>,
+    pub setup_scripts: Vec<String>,
+    pub files: Vec<PlannedFile>,
+    pub enable_services: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PlannedPackage {
+    pub name: String,
+    pub version: String,
+    pub file_name: String,
+    pub url: String,
+    pub size: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PlannedFile {
+    pub path: PathBuf,
+    pub change: Change,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Create,
+    Overwrite,
+    Unchanged,
+}
+
+impl Change {
+    fn marker(&self) -> &'static str {
+        match self {
+            Change::Create => "+",
+            Change::Overwrite => "~",
+            Change::Unchanged => "=",
+        }
+    }
+}
+
+/// Shell scripts in `dir`, in the order they are run.
+pub fn scripts(dir: &Path) -> Vec<PathBuf> {
+    WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| e.into_path())
+        .filter(|p| p.is_file() && p.extension().unwrap_or_default() == "sh")
+        .collect()
+}
+
+impl Plan {
+    /// Work out the plan from the packages downloaded and extracted into `tmp_dir`,
+    /// comparing their sysroot against the files under `root`.
+    pub fn build(
+        source: String,
+        packages: &[Package],
+        tmp_dir: &Path,
+        root: &Path,
+        stop_services: Vec<String>,
+        enable_services: Vec<String>,
+    ) -> anyhow::Result<Plan, anyhow::Error> {
+        let build_dir = tmp_dir.join("build");
+        let relative = |p: PathBuf| {
+            p.strip_prefix(&build_dir)
+                .unwrap_or(&p)
+                .display()
+                .to_string()
+        };
+
+        let mut planned = vec![];
+        for p in packages.iter() {
+            planned.push(PlannedPackage {
+                name: p.name.clone(),
+                version: p.version.clone(),
+                file_name: p.file_name.clone(),
+                url: p.location(),
+                size: std::fs::metadata(tmp_dir.join(&p.file_name))?.len(),
+            });
+        }
+
+        let sysroot_dir = build_dir.join(SYSROOT_DIR);
+        let mut files = vec![];
+        for entry in WalkDir::new(&sysroot_dir)
+            .sort_by_file_name()
+            .min_depth(1)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| !e.file_type().is_dir())
+        {
+            let path = Path::new("/").join(entry.path().strip_prefix(&sysroot_dir)?);
+            let dest = root.join(path.strip_prefix("/")?);
+            let change = if !dest.exists() {
+                Change::Create
+            } else if std::fs::read(&dest).ok() == std::fs::read(entry.path()).ok() {
+                Change::Unchanged
+            } else {
+                Change::Overwrite
+            };
+            files.push(PlannedFile { path, change });
+        }
+
+        Ok(Plan {
+            source,
+            packages: planned,
+            stop_services,
+            migration_scripts: migration::scripts(&build_dir.join(MIGRATION_SCRIPT_DIR))
+                .into_iter()
+                .map(relative)
+                .collect(),
+            setup_scripts: scripts(&build_dir.join(SETUP_SCRIPT_DIR))
+                .into_iter()
+                .map(relative)
+                .collect(),
+            files,
+            enable_services,
+        })
+    }
+
+    pub fn print(&self) {
+        print_output!("{} {}", style("Source:").bold(), self.source);
+
+        print_output!("{}", style("Packages:").bold());
+        for p in self.packages.iter() {
+            print_output!(
+                "  {} {}  {}  {}",
+                p.name,
+                p.version,
+                p.file_name,
+                HumanBytes(p.size)
+            );
+        }
+
+        let sections = [
+            ("Services to stop:", &self.stop_services),
+            ("Migration scripts:", &self.migration_scripts),
+            ("Setup scripts:", &self.setup_scripts),
+        ];
+        for (title, items) in sections {
+            print_output!("{}", style(title).bold());
+            for item in items.iter() {
+                print_output!("  {}", item);
+            }
+        }
+
+        print_output!(
+            "{} (+ create, ~ overwrite, = unchanged)",
+            style("Files:").bold()
+        );
+        for f in self.files.iter() {
+            print_output!("  {} {}", f.change.marker(), f.path.display());
+        }
+
+        print_output!("{}", style("Services to enable:").bold());
+        for service in self.enable_services.iter() {
+            print_output!("  {}", service);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_build_plan() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let build_dir = tmp_dir.path().join("build");
+        for (path, content) in [
+            ("scripts/migration/script.d/02-b.sh", "true"),
+            ("scripts/migration/script.d/01-a.sh", "true"),
+            ("scripts/migration/script.d/README", ""),
+            ("scripts/setup/script.d/01-setup.sh", "true"),
+            ("sysroot/usr/bin/casaos", "new binary"),
+            ("sysroot/etc/casaos/gateway.ini", "port=80"),
+            ("sysroot/etc/casaos/casaos.conf", "same"),
+        ] {
+            let file = build_dir.join(path);
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(file, content).unwrap();
+        }
+        std::fs::create_dir_all(root.path().join("etc/casaos")).unwrap();
+        std::fs::write(root.path().join("etc/casaos/gateway.ini"), "port=8080").unwrap();
+        std::fs::write(root.path().join("etc/casaos/casaos.conf"), "same").unwrap();
+        std::fs::write(
+            tmp_dir.path().join("linux-amd64-casaos-v0.4.4.tar.gz"),
+            "1234",
+        )
+        .unwrap();
+
+        let package = Package::test("https://example.com");
+        let plan = Plan::build(
+            "https://example.com/".to_string(),
+            &[package],
+            tmp_dir.path(),
+            root.path(),
+            vec!["casaos.service".to_string()],
+            vec!["casaos.service".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(plan.packages[0].size, 4);
+        assert_eq!(
+            plan.migration_scripts,
+            [
+                "scripts/migration/script.d/01-a.sh",
+                "scripts/migration/script.d/02-b.sh"
+            ]
+        );
+        assert_eq!(plan.setup_scripts, ["scripts/setup/script.d/01-setup.sh"]);
+        let files = plan
+            .files
+            .iter()
+            .map(|f| (f.path.to_str().unwrap(), f.change))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            [
+                ("/etc/casaos/casaos.conf", Change::Unchanged),
+                ("/etc/casaos/gateway.ini", Change::Overwrite),
+                ("/usr/bin/casaos", Change::Create),
+            ]
+        );
+        let json = serde_json::to_value(&plan).unwrap();
+        assert_eq!(json["files"][1]["change"], "overwrite");
+    }
+}
@@ -0,0 +1,697 @@
+use crate::{
+    print_fail, print_ok, print_warn,
+    utils::{arch, docker, gateway, oplog, output::status_term, package::Manager},
+};
+use console::style;
+use serde::Serialize;
+use std::{
+    net::ToSocketAddrs,
+    ops::Div,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use sys_info::linux_os_release;
+
+/// Ports CasaOS needs to be free, the gateway listens on 80 by default.
+pub const DEFAULT_PORTS: &[u16] = &[gateway::DEFAULT_PORT];
+const MB: u64 = 1024 * 1024;
+const GB: u64 = 1024 * MB;
+
+/// Host resolved to tell whether DNS works.
+pub const DNS_PROBE_HOST: &str = "github.com";
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// What a check found, with a hint on how to fix it unless it passed.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub status: Status,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl Finding {
+    pub fn pass(message: impl Into<String>) -> Finding {
+        Finding {
+            status: Status::Pass,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    pub fn warn(message: impl Into<String>, hint: impl Into<String>) -> Finding {
+        Finding {
+            status: Status::Warn,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    pub fn fail(message: impl Into<String>, hint: impl Into<String>) -> Finding {
+        Finding {
+            status: Status::Fail,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// A single pre-flight check of the host.
+pub trait Check {
+    /// Short stable name, used in the JSON output.
+    fn id(&self) -> &'static str;
+    fn run(&self) -> Finding;
+}
+
+/// Settings of the checks that depend on the install.
+pub struct Options {
+    /// Root of the host's `/proc`, `/sys`, `/run` and `/etc`, `/` outside of tests.
+    pub root: PathBuf,
+    /// Where app data is kept, `/DATA` unless a profile moves it.
+    pub data_dir: PathBuf,
+    pub docker_min_version: String,
+    pub ports: Vec<u16>,
+    pub dns_host: String,
+}
+
+/// All checks, in the order they are reported.
+pub fn checks(options: &Options) -> Vec<Box<dyn Check>> {
+    let root = options.root.clone();
+    vec![
+        Box::new(Arch),
+        Box::new(Distro),
+        Box::new(Memory),
+        Box::new(Disk::new(
+            "disk-usr",
+            root.join("usr"),
+            500 * MB,
+            10_000,
+            true,
+        )),
+        Box::new(Disk::new(
+            "disk-casaos",
+            root.join("var/lib/casaos"),
+            GB,
+            10_000,
+            false,
+        )),
+        Box::new(Disk::new(
+            "disk-tmp",
+            std::env::temp_dir(),
+            500 * MB,
+            1_000,
+            true,
+        )),
+        Box::new(Disk::new(
+            "disk-data",
+            root.join(
+                options
+                    .data_dir
+                    .strip_prefix("/")
+                    .unwrap_or(&options.data_dir),
+            ),
+            5 * GB,
+            50_000,
+            false,
+        )),
+        Box::new(Docker {
+            min_version: options.docker_min_version.clone(),
+        }),
+        Box::new(Systemd { root: root.clone() }),
+        Box::new(AppArmor { root: root.clone() }),
+        Box::new(Ports {
+            root: root.clone(),
+            ports: options.ports.clone(),
+        }),
+        Box::new(Dns {
+            host: options.dns_host.clone(),
+        }),
+        Box::new(TimeSync),
+        Box::new(Cgroups { root }),
+    ]
+}
+
+#[derive(Serialize, Debug)]
+pub struct CheckResult {
+    pub id: &'static str,
+    #[serde(flatten)]
+    pub finding: Finding,
+}
+
+/// Results of all checks that were run.
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub status: Status,
+    pub checks: Vec<CheckResult>,
+}
+
+/// Run `checks` in order.
+pub fn run(checks: &[Box<dyn Check>]) -> Report {
+    let checks = checks
+        .iter()
+        .map(|c| CheckResult {
+            id: c.id(),
+            finding: c.run(),
+        })
+        .collect::<Vec<_>>();
+    Report {
+        status: checks
+            .iter()
+            .map(|c| c.finding.status)
+            .max()
+            .unwrap_or(Status::Pass),
+        checks,
+    }
+}
+
+impl Report {
+    /// 0 when every check passed, 1 with warnings and 2 with failures.
+    pub fn exit_code(&self) -> i32 {
+        match self.status {
+            Status::Pass => 0,
+            Status::Warn => 1,
+            Status::Fail => 2,
+        }
+    }
+
+    pub fn print(&self) {
+        let console = status_term();
+        for check in self.checks.iter() {
+            let finding = &check.finding;
+            match finding.status {
+                Status::Pass => print_ok!("{:<12} {}", check.id, finding.message),
+                Status::Warn => print_warn!("{:<12} {}", check.id, finding.message),
+                Status::Fail => print_fail!("{:<12} {}", check.id, finding.message),
+            }
+            if let Some(hint) = &finding.hint {
+                oplog::message("hint", hint);
+                console
+                    .write_line(&format!("{:>20}{}", "", style(hint).dim()))
+                    .unwrap();
+            }
+        }
+    }
+}
+
+struct Arch;
+
+impl Check for Arch {
+    fn id(&self) -> &'static str {
+        "arch"
+    }
+
+    fn run(&self) -> Finding {
+        let cpu = arch::detect();
+        match cpu.arch {
+            Some(arch) => Finding::pass(format!("{} ({})", cpu.name, arch)),
+            None => Finding::fail(
+                format!("{} is not supported", cpu.name),
+                "CasaOS is built for x86_64, aarch64 and ARMv7 or newer",
+            ),
+        }
+    }
+}
+
+struct Distro;
+
+impl Check for Distro {
+    fn id(&self) -> &'static str {
+        "distro"
+    }
+
+    fn run(&self) -> Finding {
+        let Ok(release) = linux_os_release() else {
+            return Finding::warn(
+                "Failed to read /etc/os-release",
+                "Dependencies have to be installed by hand",
+            );
+        };
+        let id = release.id.unwrap_or_default();
+        let name = release.pretty_name.unwrap_or_else(|| id.clone());
+        match Manager::detect(&id, &release.id_like.unwrap_or_default()) {
+            Some(_) => Finding::pass(name),
+            None => Finding::warn(
+                format!("{} is not a tested distribution", name),
+                "Dependencies have to be installed by hand, Debian and Ubuntu are recommended",
+            ),
+        }
+    }
+}
+
+struct Memory;
+
+impl Check for Memory {
+    fn id(&self) -> &'static str {
+        "memory"
+    }
+
+    fn run(&self) -> Finding {
+        let Ok(info) = sys_info::mem_info() else {
+            return Finding::warn(
+                "Failed to read the memory size",
+                "At least 400MB are required",
+            );
+        };
+        let memory = (info.total as f64).div(1024.0);
+        if memory < 400.0 {
+            Finding::fail(
+                format!("{:.0}MB physical memory", memory),
+                "Requires at least 400MB physical memory",
+            )
+        } else {
+            Finding::pass(format!("{:.0}MB physical memory", memory))
+        }
+    }
+}
+
+/// Free space and inodes on the filesystem holding `path`, which may not exist yet.
+struct Disk {
+    id: &'static str,
+    path: PathBuf,
+    min_free: u64,
+    min_inodes: u64,
+    /// The install can't complete without the space, otherwise it is a recommendation.
+    required: bool,
+}
+
+impl Disk {
+    fn new(
+        id: &'static str,
+        path: PathBuf,
+        min_free: u64,
+        min_inodes: u64,
+        required: bool,
+    ) -> Disk {
+        Disk {
+            id,
+            path,
+            min_free,
+            min_inodes,
+            required,
+        }
+    }
+}
+
+impl Check for Disk {
+    fn id(&self) -> &'static str {
+        self.id
+    }
+
+    fn run(&self) -> Finding {
+        let path = self.path.display();
+        let space = match space(&self.path) {
+            Ok(space) => space,
+            Err(e) => {
+                return Finding::warn(
+                    format!("Failed to read the free space of {}: {}", path, e),
+                    format!("{} should have {} free", path, size(self.min_free)),
+                )
+            }
+        };
+        let message = format!(
+            "{} free on {}, {} inodes",
+            size(space.free),
+            path,
+            space.free_inodes
+        );
+        // Filesystems like btrfs allocate inodes on demand and report none.
+        let low_inodes = space.total_inodes > 0 && space.free_inodes < self.min_inodes;
+        if space.free >= self.min_free && !low_inodes {
+            return Finding::pass(message);
+        }
+        let hint = format!(
+            "{} needs {} and {} inodes free, free up space or mount a larger disk there",
+            path,
+            size(self.min_free),
+            self.min_inodes
+        );
+        if self.required {
+            Finding::fail(message, hint)
+        } else {
+            Finding::warn(message, hint)
+        }
+    }
+}
+
+pub struct Space {
+    /// Bytes available to unprivileged users.
+    pub free: u64,
+    pub free_inodes: u64,
+    pub total_inodes: u64,
+}
+
+/// Free space of the filesystem holding `path`, or its nearest existing parent.
+pub fn space(path: &Path) -> std::io::Result<Space> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let existing = path
+        .ancestors()
+        .find(|p| p.exists())
+        .unwrap_or(Path::new("/"));
+    let c_path = std::ffi::CString::new(existing.as_os_str().as_bytes())?;
+    // SAFETY: statvfs only writes into the zeroed struct it is given.
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    #[allow(clippy::unnecessary_cast)]
+    Ok(Space {
+        free: stat.f_bavail as u64 * stat.f_frsize as u64,
+        free_inodes: stat.f_favail as u64,
+        total_inodes: stat.f_files as u64,
+    })
+}
+
+fn size(bytes: u64) -> String {
+    if bytes >= GB {
+        format!("{:.2}GB", (bytes as f64).div(GB as f64))
+    } else {
+        format!("{:.0}MB", (bytes as f64).div(MB as f64))
+    }
+}
+
+struct Docker {
+    min_version: String,
+}
+
+impl Check for Docker {
+    fn id(&self) -> &'static str {
+        "docker"
+    }
+
+    fn run(&self) -> Finding {
+        let installation = docker::detect();
+        let hint = "`yacc install` installs it, see --docker-from";
+        let Some(engine) = installation.engine else {
+            return Finding::warn("Docker is not installed", hint);
+        };
+        if !docker::satisfies(&engine, &self.min_version) {
+            return Finding::warn(
+                format!("Docker {} is older than {}", engine, self.min_version),
+                hint,
+            );
+        }
+        if installation.compose.is_none() {
+            return Finding::warn("The Docker compose plugin is not installed", hint);
+        }
+        if docker::ping(Path::new(docker::SOCKET)).is_err() {
+            return Finding::warn(
+                format!("Docker {} is not running", engine),
+                "Start it with `systemctl enable --now docker`",
+            );
+        }
+        Finding::pass(format!("Docker {}", engine))
+    }
+}
+
+struct Systemd {
+    root: PathBuf,
+}
+
+impl Check for Systemd {
+    fn id(&self) -> &'static str {
+        "systemd"
+    }
+
+    fn run(&self) -> Finding {
+        // Present only when systemd is the running init.
+        if self.root.join("run/systemd/system").is_dir() {
+            Finding::pass("systemd is running")
+        } else {
+            Finding::fail(
+                "systemd is not running",
+                "CasaOS services are managed by systemd, use --root to install into an image",
+            )
+        }
+    }
+}
+
+struct AppArmor {
+    root: PathBuf,
+}
+
+impl Check for AppArmor {
+    fn id(&self) -> &'static str {
+        "apparmor"
+    }
+
+    fn run(&self) -> Finding {
+        let enabled =
+            std::fs::read_to_string(self.root.join("sys/module/apparmor/parameters/enabled"))
+                .is_ok_and(|e| e.trim() == "Y");
+        if !enabled {
+            return Finding::pass("AppArmor is not enabled");
+        }
+        let parser = ["sbin/apparmor_parser", "usr/sbin/apparmor_parser"]
+            .iter()
+            .any(|p| self.root.join(p).exists());
+        if parser {
+            Finding::pass("AppArmor is enabled")
+        } else {
+            Finding::warn(
+                "AppArmor is enabled but apparmor_parser is missing",
+                "Docker can't load its profile, install the apparmor package",
+            )
+        }
+    }
+}
+
+struct Ports {
+    root: PathBuf,
+    ports: Vec<u16>,
+}
+
+impl Check for Ports {
+    fn id(&self) -> &'static str {
+        "ports"
+    }
+
+    fn run(&self) -> Finding {
+        let listening = listening_ports(&self.root);
+        let busy = self
+            .ports
+            .iter()
+            .filter(|p| listening.contains(p))
+            .map(|p| p.to_string())
+            .collect::<Vec<_>>();
+        let ports = self
+            .ports
+            .iter()
+            .map(|p| p.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        if busy.is_empty() {
+            Finding::pass(format!("Port {} free", ports))
+        } else {
+            Finding::warn(
+                format!("Port {} in use", busy.join(", ")),
+                "Stop the service using it, unless it is an installed CasaOS gateway",
+            )
+        }
+    }
+}
+
+/// TCP ports listened on, from `/proc/net/tcp` and `/proc/net/tcp6` under `root`.
+pub fn listening_ports(root: &Path) -> Vec<u16> {
+    let mut ports = vec![];
+    for file in ["proc/net/tcp", "proc/net/tcp6"] {
+        let Ok(content) = std::fs::read_to_string(root.join(file)) else {
+            continue;
+        };
+        for line in content.lines().skip(1) {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            // 0A is TCP_LISTEN
+            if fields.len() < 4 || fields[3] != "0A" {
+                continue;
+            }
+            let port = fields[1]
+                .rsplit_once(':')
+                .and_then(|(_, port)| u16::from_str_radix(port, 16).ok());
+            if let Some(port) = port.filter(|p| !ports.contains(p)) {
+                ports.push(port);
+            }
+        }
+    }
+    ports
+}
+
+struct Dns {
+    host: String,
+}
+
+impl Check for Dns {
+    fn id(&self) -> &'static str {
+        "dns"
+    }
+
+    fn run(&self) -> Finding {
+        match (self.host.as_str(), 443).to_socket_addrs() {
+            Ok(_) => Finding::pass(format!("{} resolves", self.host)),
+            Err(e) => Finding::warn(
+                format!("Failed to resolve {}: {}", self.host, e),
+                "Check the nameservers in /etc/resolv.conf, or install from a bundle",
+            ),
+        }
+    }
+}
+
+struct TimeSync;
+
+impl Check for TimeSync {
+    fn id(&self) -> &'static str {
+        "time"
+    }
+
+    fn run(&self) -> Finding {
+        let hint = "Enable time sync with `timedatectl set-ntp true`, TLS fails with a wrong clock";
+        let output = oplog::capture(Command::new("timedatectl").args([
+            "show",
+            "--property",
+            "NTPSynchronized",
+            "--value",
+        ]));
+        match output {
+            Ok(o) if String::from_utf8_lossy(&o.stdout).trim() == "yes" => {
+                Finding::pass("Clock is synchronized")
+            }
+            Ok(o) if o.status.success() => Finding::warn("Clock is not synchronized", hint),
+            _ => Finding::warn("Failed to query time sync with timedatectl", hint),
+        }
+    }
+}
+
+struct Cgroups {
+    root: PathBuf,
+}
+
+impl Check for Cgroups {
+    fn id(&self) -> &'static str {
+        "cgroups"
+    }
+
+    fn run(&self) -> Finding {
+        let cgroup = self.root.join("sys/fs/cgroup");
+        let (version, controllers) =
+            match std::fs::read_to_string(cgroup.join("cgroup.controllers")) {
+                Ok(controllers) => (
+                    2,
+                    controllers.split_whitespace().map(str::to_string).collect(),
+                ),
+                // cgroup v1 mounts a hierarchy per controller.
+                Err(_) => (
+                    1,
+                    ["cpu", "memory", "pids"]
+                        .iter()
+                        .filter(|c| cgroup.join(c).is_dir())
+                        .map(|c| c.to_string())
+                        .collect::<Vec<_>>(),
+                ),
+            };
+        let missing = ["cpu", "memory", "pids"]
+            .into_iter()
+            .filter(|c| !controllers.iter().any(|have| have == c))
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            Finding::pass(format!("cgroup v{} with cpu, memory and pids", version))
+        } else {
+            Finding::warn(
+                format!("cgroup v{} without {}", version, missing.join(", ")),
+                "Add `cgroup_enable=memory cgroup_memory=1` to the kernel command line, Docker can't limit apps without them",
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_listening_ports() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("proc/net")).unwrap();
+        std::fs::write(
+            dir.path().join("proc/net/tcp"),
+            "  sl  local_address rem_address   st tx_queue rx_queue\n   0: 00000000:0050 00000000:0000 0A 00000000:00000000\n   1: 0100007F:1F90 0100007F:C350 01 00000000:00000000\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("proc/net/tcp6"),
+            "  sl  local_address rem_address   st\n   0: 00000000000000000000000000000000:01BB 00000000000000000000000000000000:0000 0A\n",
+        )
+        .unwrap();
+        assert_eq!(listening_ports(dir.path()), [80, 443]);
+
+        let ports = Ports {
+            root: dir.path().to_path_buf(),
+            ports: vec![80, 8080],
+        };
+        assert_eq!(ports.run().status, Status::Warn);
+    }
+
+    #[test]
+    fn test_cgroups() {
+        let dir = tempfile::tempdir().unwrap();
+        let cgroup = dir.path().join("sys/fs/cgroup");
+        std::fs::create_dir_all(&cgroup).unwrap();
+        std::fs::write(cgroup.join("cgroup.controllers"), "cpuset cpu io pids\n").unwrap();
+        let finding = Cgroups {
+            root: dir.path().to_path_buf(),
+        }
+        .run();
+        assert_eq!(finding.status, Status::Warn);
+        assert_eq!(finding.message, "cgroup v2 without memory");
+    }
+
+    #[test]
+    fn test_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        // Missing paths are checked on the filesystem they will be created on.
+        let missing = dir.path().join("var/lib/casaos");
+        assert!(space(&missing).unwrap().free > 0);
+
+        let enough = Disk::new("disk-casaos", missing.clone(), 1, 0, true);
+        assert_eq!(enough.run().status, Status::Pass);
+        let optional = Disk::new("disk-data", missing.clone(), u64::MAX, 0, false);
+        assert_eq!(optional.run().status, Status::Warn);
+        let required = Disk::new("disk-tmp", missing, u64::MAX, 0, true);
+        assert_eq!(required.run().status, Status::Fail);
+    }
+
+    struct Fixed(Status);
+
+    impl Check for Fixed {
+        fn id(&self) -> &'static str {
+            "fixed"
+        }
+
+        fn run(&self) -> Finding {
+            Finding {
+                status: self.0,
+                message: String::new(),
+                hint: None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_report_status() {
+        let checks: Vec<Box<dyn Check>> = vec![Box::new(Fixed(Status::Pass))];
+        assert_eq!(run(&checks).exit_code(), 0);
+        let checks: Vec<Box<dyn Check>> = vec![
+            Box::new(Fixed(Status::Warn)),
+            Box::new(Fixed(Status::Fail)),
+            Box::new(Fixed(Status::Pass)),
+        ];
+        let report = run(&checks);
+        assert_eq!(report.status, Status::Fail);
+        assert_eq!(report.exit_code(), 2);
+    }
+}
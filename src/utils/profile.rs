@@ -0,0 +1,184 @@
+use crate::{consts::CASA_PACKAGES, utils::confirm};
+use anyhow::Context;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+/// Release channel of a profile.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    Stable,
+    Alpha,
+}
+
+/// Commands run around the install, with `sh -c`.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    #[serde(default)]
+    pub pre_install: Vec<String>,
+    #[serde(default)]
+    pub post_install: Vec<String>,
+}
+
+/// Settings of an unattended install, read from `install --profile`.
+/// Command line flags and environment variables take precedence.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Download mirrors, tried in order of latency.
+    #[serde(default)]
+    pub mirrors: Vec<String>,
+    pub channel: Option<Channel>,
+    /// Newest CasaOS version to install.
+    pub version: Option<String>,
+    /// Versions pinned per component, e.g. `CasaOS-Gateway = "0.4.2"`.
+    #[serde(default)]
+    pub components: BTreeMap<String, String>,
+    pub gateway_port: Option<u16>,
+    /// Where CasaOS keeps app data, linked from `/DATA` when it is elsewhere.
+    pub data_dir: Option<PathBuf>,
+    /// Install or upgrade Docker when needed, on by default.
+    pub docker: Option<bool>,
+    /// Install rclone when it is missing, on by default.
+    pub rclone: Option<bool>,
+    /// Packages installed along with the CasaOS dependencies.
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+    #[serde(default)]
+    pub hooks: Hooks,
+    /// Answers to prompts by ID, like an answer file.
+    #[serde(default)]
+    pub answers: HashMap<String, bool>,
+}
+
+impl Profile {
+    /// Read and validate the profile at `path`, reporting every problem at once.
+    pub fn load(path: &Path) -> anyhow::Result<Profile, anyhow::Error> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read profile {}", path.display()))?;
+        let profile: Profile = toml::from_str(&content)
+            .with_context(|| format!("Invalid profile {}", path.display()))?;
+        let errors = profile.validate();
+        if !errors.is_empty() {
+            anyhow::bail!(
+                "Invalid profile {}:\n  - {}",
+                path.display(),
+                errors.join("\n  - ")
+            );
+        }
+        Ok(profile)
+    }
+
+    /// Problems the schema can't express.
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        for mirror in self.mirrors.iter() {
+            if !mirror.starts_with("http://") && !mirror.starts_with("https://") {
+                errors.push(format!("mirror `{}` is not an http(s) url", mirror));
+            }
+        }
+        if self.version.as_deref().is_some_and(|v| v.trim().is_empty()) {
+            errors.push("version is empty".to_string());
+        }
+        for name in self.components.keys() {
+            if !CASA_PACKAGES.contains(&name.as_str()) {
+                errors.push(format!(
+                    "unknown component `{}`, expected one of: {}",
+                    name,
+                    CASA_PACKAGES.join(", ")
+                ));
+            }
+        }
+        if self.gateway_port == Some(0) {
+            errors.push("gateway_port must be between 1 and 65535".to_string());
+        }
+        if self.data_dir.as_ref().is_some_and(|d| !d.is_absolute()) {
+            errors.push("data_dir must be an absolute path".to_string());
+        }
+        for dependency in self.dependencies.iter() {
+            if dependency.is_empty() || dependency.contains(char::is_whitespace) {
+                errors.push(format!("dependency `{}` is not a package name", dependency));
+            }
+        }
+        let hooks = self
+            .hooks
+            .pre_install
+            .iter()
+            .chain(&self.hooks.post_install);
+        if hooks.into_iter().any(|h| h.trim().is_empty()) {
+            errors.push("hooks must not be empty".to_string());
+        }
+        for id in confirm::unknown_prompts(self.answers.keys()) {
+            errors.push(format!("unknown prompt `{}` in answers", id));
+        }
+        errors
+    }
+
+    pub fn install_docker(&self) -> bool {
+        self.docker.unwrap_or(true)
+    }
+
+    pub fn install_rclone(&self) -> bool {
+        self.rclone.unwrap_or(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_load_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("casaos.toml");
+        std::fs::write(
+            &path,
+            r#"
+mirrors = ["https://mirror.example.com/IceWhaleTech/"]
+channel = "alpha"
+gateway_port = 8080
+data_dir = "/srv/casaos"
+rclone = false
+dependencies = ["htop"]
+
+[components]
+CasaOS-Gateway = "0.4.2"
+
+[hooks]
+post_install = ["systemctl restart tailscaled"]
+
+[answers]
+install_docker = true
+"#,
+        )
+        .unwrap();
+        let profile = Profile::load(&path).unwrap();
+        assert_eq!(profile.channel, Some(Channel::Alpha));
+        assert_eq!(profile.gateway_port, Some(8080));
+        assert!(profile.install_docker());
+        assert!(!profile.install_rclone());
+        assert_eq!(profile.hooks.post_install.len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("casaos.toml");
+        std::fs::write(&path, "gateway = 8080\n").unwrap();
+        assert!(Profile::load(&path).is_err());
+
+        std::fs::write(
+            &path,
+            "mirrors = [\"ftp://mirror\"]\ndata_dir = \"data\"\n[components]\nCasaOS-Nope = \"1.0\"\n",
+        )
+        .unwrap();
+        let error = format!("{:#}", Profile::load(&path).unwrap_err());
+        assert!(error.contains("mirror `ftp://mirror`"));
+        assert!(error.contains("data_dir must be"));
+        assert!(error.contains("unknown component `CasaOS-Nope`"));
+    }
+}
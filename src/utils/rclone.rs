@@ -0,0 +1,197 @@
+use crate::utils::{bundle, checksum, mirror, release::Package};
+use anyhow::Context;
+use reqwest::Client;
+use std::{fs::File, path::Path};
+
+/// rclone release installed along with CasaOS.
+pub const VERSION: &str = "1.65.2";
+/// rclone's own download site, always tried last.
+pub const DOWNLOADS: &str = "https://downloads.rclone.org/";
+/// Where the binary is installed, relative to the root.
+pub const BINARY: &str = "usr/bin/rclone";
+pub const UNIT: &str = "rclone.service";
+/// Where a missing unit is created, relative to the root.
+pub const UNIT_PATH: &str = "etc/systemd/system/rclone.service";
+
+/// Unit serving the rclone remote control API to CasaOS.
+pub const UNIT_FILE: &str = r#"[Unit]
+Description=rclone
+After=network-online.target
+
+[Service]
+ExecStartPre=/usr/bin/mkdir -p /var/run/rclone
+ExecStartPre=/usr/bin/rm -f /var/run/rclone/rclone.sock
+ExecStart=/usr/bin/rclone rcd --rc-addr unix:///var/run/rclone/rclone.sock --rc-no-auth --rc-allow-origin "*"
+Restart=always
+RestartSec=10
+
+[Install]
+WantedBy=multi-user.target
+"#;
+
+/// Name of the release archive, e.g. `rclone-v1.65.2-linux-arm-v7.zip`.
+pub fn file_name(version: &str, arch: &str) -> String {
+    let arch = match arch {
+        "arm-7" => "arm-v7",
+        arch => arch,
+    };
+    format!("rclone-v{}-linux-{}.zip", version, arch)
+}
+
+/// Where rclone releases are found on each mirror, with rclone's own site last.
+pub fn base_urls(mirrors: &[String]) -> Vec<String> {
+    let mut urls = mirrors
+        .iter()
+        .map(|m| match m.as_str() {
+            mirror::GITHUB => "https://github.com/rclone/rclone/releases/download/".to_string(),
+            mirror::ALIYUN => "https://casaos.oss-cn-shanghai.aliyuncs.com/".to_string(),
+            m => format!("{}rclone/releases/download/", m),
+        })
+        .collect::<Vec<_>>();
+    urls.push(DOWNLOADS.to_string());
+    urls.dedup();
+    urls
+}
+
+/// The rclone release archive for `arch`, downloadable from all mirrors.
+pub fn package(mirrors: &[String], arch: &str, version: &str) -> Package {
+    let file_name = file_name(version, arch);
+    let mut urls = base_urls(mirrors)
+        .into_iter()
+        .map(|base| format!("{}v{}/{}", base, version, file_name));
+    Package {
+        name: bundle::RCLONE.to_string(),
+        version: version.to_string(),
+        url: urls.next().unwrap(),
+        fallback_urls: urls.collect(),
+        path: None,
+        file_name,
+        sha256: None,
+    }
+}
+
+/// Digest of the archive from the `SHA256SUMS` of its release under `base`, `None` if
+/// there is none. Pass `DOWNLOADS`, so a mirror serving a tampered archive can't serve
+/// a matching digest too.
+pub async fn expected_digest(
+    client: &Client,
+    package: &Package,
+    base: &str,
+) -> anyhow::Result<Option<String>, anyhow::Error> {
+    if let Some(digest) = &package.sha256 {
+        return Ok(Some(digest.to_lowercase()));
+    }
+    let url = format!("{}v{}/SHA256SUMS", base, package.version);
+    let response = client
+        .get(&url)
+        .send()
+        .await
+        .with_context(|| format!("Failed to fetch {}", url))?;
+    if !response.status().is_success() {
+        return Ok(None);
+    }
+    // The file is clearsigned, the signature lines never match an archive name.
+    let checksums = checksum::parse_checksums(&response.text().await?);
+    Ok(checksums.get(&package.file_name).cloned())
+}
+
+/// Extract the `rclone` binary from a release archive to `dest`.
+pub fn extract(archive: &Path, dest: &Path) -> anyhow::Result<(), anyhow::Error> {
+    let file =
+        File::open(archive).with_context(|| format!("Failed to open {}", archive.display()))?;
+    let mut zip = zip::ZipArchive::new(file)
+        .with_context(|| format!("Invalid archive {}", archive.display()))?;
+    let name = zip
+        .file_names()
+        .find(|n| n.rsplit('/').next() == Some("rclone"))
+        .map(str::to_string)
+        .with_context(|| format!("No rclone binary in {}", archive.display()))?;
+    let mut out = File::create(dest)?;
+    std::io::copy(&mut zip.by_name(&name)?, &mut out)?;
+
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(dest, std::fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+/// Whether the rclone binary is installed under `root`.
+pub fn installed(root: &Path) -> bool {
+    root.join(BINARY).exists()
+}
+
+/// Whether a unit file for rclone exists under `root`.
+pub fn unit_exists(root: &Path) -> bool {
+    [
+        UNIT_PATH,
+        "usr/lib/systemd/system/rclone.service",
+        "lib/systemd/system/rclone.service",
+    ]
+    .iter()
+    .any(|p| root.join(p).exists())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::test_server::TestServer;
+    use std::io::Write;
+
+    #[test]
+    fn test_package_urls() {
+        let package = package(
+            &[mirror::ALIYUN.to_string(), mirror::GITHUB.to_string()],
+            "arm-7",
+            "1.65.2",
+        );
+        assert_eq!(package.file_name, "rclone-v1.65.2-linux-arm-v7.zip");
+        assert_eq!(
+            package.url,
+            "https://casaos.oss-cn-shanghai.aliyuncs.com/v1.65.2/rclone-v1.65.2-linux-arm-v7.zip"
+        );
+        assert_eq!(
+            package.fallback_urls,
+            [
+                "https://github.com/rclone/rclone/releases/download/v1.65.2/rclone-v1.65.2-linux-arm-v7.zip",
+                "https://downloads.rclone.org/v1.65.2/rclone-v1.65.2-linux-arm-v7.zip",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expected_digest_from_signed_sums() {
+        let sums = "-----BEGIN PGP SIGNED MESSAGE-----\nHash: SHA1\n\nabc123  rclone-v1.65.2-linux-amd64.zip\ndef456  rclone-v1.65.2-linux-arm64.zip\n-----BEGIN PGP SIGNATURE-----\n";
+        let server = TestServer::start(vec![
+            ("/v1.65.2/SHA256SUMS", sums.into()),
+            (
+                "/rclone/releases/download/v1.65.2/SHA256SUMS",
+                "000000  rclone-v1.65.2-linux-amd64.zip\n".into(),
+            ),
+        ]);
+        let package = package(&[server.url("/")], "amd64", "1.65.2");
+        let digest = expected_digest(&Client::new(), &package, &server.url("/"))
+            .await
+            .unwrap();
+        assert_eq!(digest.as_deref(), Some("abc123"));
+    }
+
+    #[test]
+    fn test_extract() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("rclone.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&archive).unwrap());
+        let options = zip::write::FileOptions::default();
+        zip.start_file("rclone-v1.65.2-linux-amd64/README.txt", options)
+            .unwrap();
+        zip.write_all(b"readme").unwrap();
+        zip.start_file("rclone-v1.65.2-linux-amd64/rclone", options)
+            .unwrap();
+        zip.write_all(b"binary").unwrap();
+        zip.finish().unwrap();
+
+        let dest = dir.path().join("rclone");
+        extract(&archive, &dest).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), b"binary");
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(dest.metadata().unwrap().permissions().mode() & 0o777, 0o755);
+    }
+}
@@ -0,0 +1,353 @@
+use crate::{
+    print_output,
+    utils::{gateway, systemd},
+};
+use console::style;
+use ini::Ini;
+use serde::Serialize;
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// Config of the message bus and the user service, relative to the root.
+pub const MESSAGE_BUS_CONF: &str = "etc/casaos/message-bus.conf";
+pub const USER_SERVICE_CONF: &str = "etc/casaos/user-service.conf";
+/// Where CasaOS services write the URL they listen on, relative to the root.
+const RUNTIME_DIR: &str = "var/run/casaos";
+/// How long each service has to become ready.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+const FIRST_RETRY: Duration = Duration::from_millis(250);
+const MAX_RETRY: Duration = Duration::from_secs(5);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// One way of telling a service is up.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Probe {
+    /// The systemd unit is active.
+    Unit(String),
+    /// Something answers HTTP at the URL, with any status below 500.
+    Http { host: String, port: u16 },
+    /// Something accepts connections on the port.
+    Tcp { host: String, port: u16 },
+    /// Something accepts connections on the port of a CasaOS service, looked up again
+    /// on every try as it may not be written yet or change when the service starts.
+    ServicePort {
+        host: String,
+        root: PathBuf,
+        conf: &'static str,
+        url_file: &'static str,
+    },
+}
+
+impl Probe {
+    fn run(&self) -> Result<String, String> {
+        match self {
+            Probe::Unit(unit) => match systemd::is_active(unit) {
+                Ok(true) => Ok(format!("{} active", unit)),
+                Ok(false) => Err(format!("{} not active", unit)),
+                Err(e) => Err(format!("{}: {}", unit, e)),
+            },
+            Probe::Http { host, port } => {
+                let status =
+                    http_status(host, *port).map_err(|e| format!("{}:{} {}", host, port, e))?;
+                match status {
+                    s if s < 500 => Ok(format!("http://{}:{} {}", host, port, s)),
+                    s => Err(format!("http://{}:{} answered {}", host, port, s)),
+                }
+            }
+            Probe::Tcp { host, port } => connect(host, *port)
+                .map(|_| format!("{}:{} open", host, port))
+                .map_err(|e| format!("{}:{} {}", host, port, e)),
+            Probe::ServicePort {
+                host,
+                root,
+                conf,
+                url_file,
+            } => match service_port(root, conf, url_file) {
+                Some(port) => Probe::Tcp {
+                    host: host.clone(),
+                    port,
+                }
+                .run(),
+                None => Err(format!(
+                    "port not known yet, none in /{} or /{}/{}",
+                    conf, RUNTIME_DIR, url_file
+                )),
+            },
+        }
+    }
+}
+
+/// A service and what must pass for it to count as ready.
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub service: String,
+    pub probes: Vec<Probe>,
+    pub timeout: Duration,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Outcome {
+    pub service: String,
+    pub ready: bool,
+    /// What the last probe found.
+    pub detail: String,
+    pub elapsed_ms: u64,
+}
+
+/// Readiness of every target, in their order.
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Report {
+    pub fn ready(&self) -> bool {
+        self.outcomes.iter().all(|o| o.ready)
+    }
+
+    pub fn print(&self) {
+        print_output!("{:<32} {:<10} {:>7}  CHECK", "SERVICE", "STATE", "TIME");
+        for o in self.outcomes.iter() {
+            let state = match o.ready {
+                true => style(format!("{:<10}", "ready")).green(),
+                false => style(format!("{:<10}", "not ready")).red(),
+            };
+            print_output!(
+                "{:<32} {} {:>6.1}s  {}",
+                o.service,
+                state,
+                o.elapsed_ms as f64 / 1000.0,
+                o.detail
+            );
+        }
+    }
+}
+
+/// Probe a target until all of its probes pass or its timeout is over,
+/// waiting twice as long after every failed round, up to a few seconds.
+pub fn wait(target: &Target) -> Outcome {
+    let started = Instant::now();
+    let mut delay = FIRST_RETRY;
+    loop {
+        let result = target
+            .probes
+            .iter()
+            .map(Probe::run)
+            .collect::<Result<Vec<_>, _>>();
+        let elapsed = started.elapsed();
+        let outcome = |ready, detail| Outcome {
+            service: target.service.clone(),
+            ready,
+            detail,
+            elapsed_ms: elapsed.as_millis() as u64,
+        };
+        match result {
+            Ok(details) => return outcome(true, details.join(", ")),
+            Err(detail) if elapsed + delay > target.timeout => return outcome(false, detail),
+            Err(_) => {}
+        }
+        std::thread::sleep(delay);
+        delay = (delay * 2).min(MAX_RETRY);
+    }
+}
+
+/// Wait for all targets at once, each with its own timeout.
+pub fn check(targets: &[Target]) -> Report {
+    let outcomes = std::thread::scope(|scope| {
+        let handles = targets
+            .iter()
+            .map(|t| scope.spawn(move || wait(t)))
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .zip(targets)
+            .map(|(h, t)| {
+                h.join().unwrap_or_else(|_| Outcome {
+                    service: t.service.clone(),
+                    ready: false,
+                    detail: "probe panicked".to_string(),
+                    elapsed_ms: 0,
+                })
+            })
+            .collect()
+    });
+    Report { outcomes }
+}
+
+/// The probes for `services` of a CasaOS installed under `root`: every unit must be
+/// active, the gateway must answer HTTP and the message bus and user service must
+/// accept connections on the ports from their config or runtime files. Units that are not installed
+/// are probed once, as waiting will not bring them.
+pub fn targets(root: &Path, services: &[&str], timeout: Duration) -> Vec<Target> {
+    services
+        .iter()
+        .map(|service| {
+            let mut probes = vec![Probe::Unit(service.to_string())];
+            let host = "127.0.0.1".to_string();
+            match *service {
+                gateway::SERVICE => probes.push(Probe::Http {
+                    host,
+                    port: gateway::port(&root.join(gateway::GATEWAY_INI))
+                        .unwrap_or(gateway::DEFAULT_PORT),
+                }),
+                "casaos-message-bus.service" => probes.push(Probe::ServicePort {
+                    host,
+                    root: root.to_path_buf(),
+                    conf: MESSAGE_BUS_CONF,
+                    url_file: "message-bus.url",
+                }),
+                "casaos-user-service.service" => probes.push(Probe::ServicePort {
+                    host,
+                    root: root.to_path_buf(),
+                    conf: USER_SERVICE_CONF,
+                    url_file: "user-service.url",
+                }),
+                _ => {}
+            }
+            Target {
+                service: service.to_string(),
+                probes,
+                timeout: match systemd::exists(service) {
+                    Ok(true) => timeout,
+                    _ => Duration::ZERO,
+                },
+            }
+        })
+        .collect()
+}
+
+/// Port of a service, from a `port` key in any section of its config, or else from
+/// the URL it wrote to the runtime directory.
+fn service_port(root: &Path, conf: &str, url_file: &str) -> Option<u16> {
+    let from_conf = Ini::load_from_file(root.join(conf)).ok().and_then(|ini| {
+        ini.iter()
+            .flat_map(|(_, props)| props.iter())
+            .find(|(k, _)| k.eq_ignore_ascii_case("port"))
+            .and_then(|(_, v)| v.trim().parse().ok())
+    });
+    from_conf.or_else(|| {
+        let url = std::fs::read_to_string(root.join(RUNTIME_DIR).join(url_file)).ok()?;
+        let authority = url.trim().split("://").nth(1)?.split('/').next()?;
+        authority.rsplit_once(':')?.1.parse().ok()
+    })
+}
+
+fn connect(host: &str, port: u16) -> std::io::Result<TcpStream> {
+    let addrs: Vec<SocketAddr> = (host, port).to_socket_addrs()?.collect();
+    let mut error = std::io::Error::from(std::io::ErrorKind::AddrNotAvailable);
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => error = e,
+        }
+    }
+    Err(error)
+}
+
+/// Status code of a plain `GET /`.
+fn http_status(host: &str, port: u16) -> std::io::Result<u16> {
+    let mut stream = connect(host, port)?;
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    // One write, as `write!` may send the request in pieces.
+    let request = format!(
+        "GET / HTTP/1.0\r\nHost: {}:{}\r\nUser-Agent: yacc\r\n\r\n",
+        host, port
+    );
+    stream.write_all(request.as_bytes())?;
+    let mut head = [0u8; 64];
+    let n = stream.read(&mut head)?;
+    let line = String::from_utf8_lossy(&head[..n]);
+    line.split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .filter(|_| line.starts_with("HTTP/"))
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "not HTTP"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_probes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut buf = [0u8; 512];
+                let _ = stream.read(&mut buf);
+                let _ = stream.write_all(b"HTTP/1.1 302 Found\r\nContent-Length: 0\r\n\r\n");
+            }
+        });
+        let host = "127.0.0.1".to_string();
+        let http = Probe::Http {
+            host: host.clone(),
+            port,
+        };
+        assert_eq!(http.run(), Ok(format!("http://127.0.0.1:{} 302", port)));
+        assert!(Probe::Tcp { host, port }.run().is_ok());
+
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed_port = closed.local_addr().unwrap().port();
+        drop(closed);
+        let target = Target {
+            service: "casaos-message-bus.service".to_string(),
+            probes: vec![Probe::Tcp {
+                host: "127.0.0.1".to_string(),
+                port: closed_port,
+            }],
+            timeout: Duration::from_millis(600),
+        };
+        let report = check(&[target]);
+        assert!(!report.ready());
+        assert!(report.outcomes[0].elapsed_ms >= 250);
+    }
+
+    #[test]
+    fn test_service_port() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join("etc/casaos")).unwrap();
+        std::fs::write(
+            root.path().join(MESSAGE_BUS_CONF),
+            "[common]\nRuntimePath=/var/run/casaos\n\n[server]\nPort=8082\n",
+        )
+        .unwrap();
+        assert_eq!(
+            service_port(root.path(), MESSAGE_BUS_CONF, "message-bus.url"),
+            Some(8082)
+        );
+
+        std::fs::create_dir_all(root.path().join(RUNTIME_DIR)).unwrap();
+        std::fs::write(
+            root.path().join(RUNTIME_DIR).join("user-service.url"),
+            "http://127.0.0.1:39617/v1/users\n",
+        )
+        .unwrap();
+        assert_eq!(
+            service_port(root.path(), USER_SERVICE_CONF, "user-service.url"),
+            Some(39617)
+        );
+        assert_eq!(service_port(root.path(), "etc/none.conf", "none.url"), None);
+
+        let probe = Probe::ServicePort {
+            host: "127.0.0.1".to_string(),
+            root: root.path().to_path_buf(),
+            conf: "etc/none.conf",
+            url_file: "none.url",
+        };
+        assert!(probe.run().unwrap_err().contains("not known yet"));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::fs::write(
+            root.path().join(RUNTIME_DIR).join("none.url"),
+            format!("http://127.0.0.1:{}/v2/message_bus", port),
+        )
+        .unwrap();
+        assert_eq!(probe.run(), Ok(format!("127.0.0.1:{} open", port)));
+    }
+}
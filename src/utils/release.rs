@@ -0,0 +1,542 @@
+use crate::print_warn;
+use anyhow::Context;
+use reqwest::Client;
+use serde::Deserialize;
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// How long to wait for a mirror to accept a connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// A release as published in the GitHub releases JSON.
+/// Mirror-hosted indexes use the same shape.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Release {
+    pub tag_name: String,
+    #[serde(default)]
+    pub prerelease: bool,
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(default)]
+    pub assets: Vec<Asset>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Asset {
+    pub name: String,
+    /// `sha256:<hex>` as reported by GitHub, if any.
+    #[serde(default)]
+    pub digest: Option<String>,
+}
+
+/// A package resolved to a concrete version and download url.
+#[derive(Debug, Clone)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    pub file_name: String,
+    pub url: String,
+    /// The same tarball on the other mirrors, tried in order when `url` fails.
+    pub fallback_urls: Vec<String>,
+    /// Digest from the release index, if it carries one.
+    pub sha256: Option<String>,
+    /// The tarball on disk when it comes from a bundle, `url` is empty then.
+    pub path: Option<PathBuf>,
+}
+
+impl Package {
+    /// `checksums.txt` published alongside the tarball.
+    pub fn checksums_url(&self) -> String {
+        match self.url.rsplit_once('/') {
+            Some((dir, _)) => format!("{}/checksums.txt", dir),
+            None => "checksums.txt".to_string(),
+        }
+    }
+
+    /// Detached minisign signature published next to the tarball.
+    pub fn signature_url(&self) -> String {
+        format!("{}.minisig", self.url)
+    }
+
+    /// Where the tarball is, its path for a bundled package and its url otherwise.
+    pub fn location(&self) -> String {
+        match &self.path {
+            Some(path) => path.display().to_string(),
+            None => self.url.clone(),
+        }
+    }
+
+    /// Detached signature next to a bundled tarball.
+    pub fn signature_path(&self) -> Option<PathBuf> {
+        let path: &Path = self.path.as_ref()?;
+        let mut name = path.file_name()?.to_os_string();
+        name.push(".minisig");
+        Some(path.with_file_name(name))
+    }
+}
+
+#[cfg(test)]
+impl Package {
+    /// CasaOS 0.4.4 for amd64, downloadable from `base`.
+    pub fn test(base: &str) -> Package {
+        let file_name = "linux-amd64-casaos-v0.4.4.tar.gz".to_string();
+        Package {
+            name: "CasaOS".to_string(),
+            version: "0.4.4".to_string(),
+            url: format!("{}/{}", base.trim_end_matches('/'), file_name),
+            file_name,
+            fallback_urls: vec![],
+            sha256: None,
+            path: None,
+        }
+    }
+}
+
+/// How versions should be picked from the release index.
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    /// Also consider pre-releases.
+    pub alpha: bool,
+    /// Only consider CasaOS releases not newer than this version.
+    pub version: Option<String>,
+    /// Exact versions for single components, by package name.
+    pub pins: HashMap<String, String>,
+    /// A single mirror-hosted index holding the releases of every package.
+    pub index: Option<String>,
+}
+
+/// Tarball name stem of a package and whether it is built per architecture.
+pub fn asset_stem(package: &str) -> Option<(&'static str, bool)> {
+    match package {
+        "CasaOS-Gateway" => Some(("casaos-gateway", true)),
+        "CasaOS-MessageBus" => Some(("casaos-message-bus", true)),
+        "CasaOS-UserService" => Some(("casaos-user-service", true)),
+        "CasaOS-LocalStorage" => Some(("casaos-local-storage", true)),
+        "CasaOS-AppManagement" => Some(("casaos-app-management", true)),
+        "CasaOS" => Some(("casaos", true)),
+        "CasaOS-CLI" => Some(("casaos-cli", true)),
+        "CasaOS-UI" => Some(("casaos", false)),
+        "CasaOS-AppStore" => Some(("appstore", false)),
+        _ => None,
+    }
+}
+
+/// Tarball name of a package release, e.g. `linux-amd64-casaos-gateway-v0.4.2.tar.gz`.
+pub fn asset_name(package: &str, arch: &str, tag: &str) -> Option<String> {
+    let (stem, per_arch) = asset_stem(package)?;
+    let arch = if per_arch { arch } else { "all" };
+    Some(format!("linux-{}-{}-{}.tar.gz", arch, stem, tag))
+}
+
+/// Release tag of a tarball named like the asset of `package` for `arch`.
+pub fn asset_tag(package: &str, arch: &str, file_name: &str) -> Option<String> {
+    let (stem, per_arch) = asset_stem(package)?;
+    let arch = if per_arch { arch } else { "all" };
+    let tag = file_name
+        .strip_prefix(&format!("linux-{}-{}-", arch, stem))?
+        .strip_suffix(".tar.gz")?;
+    let version = tag.strip_prefix('v')?;
+    if version.starts_with(|c: char| c.is_ascii_digit()) {
+        Some(tag.to_string())
+    } else {
+        None
+    }
+}
+
+/// Url of the release index of a package on the given download domain.
+/// GitHub is queried through its API, mirrors serve `releases/index.json`.
+pub fn index_url(download_domain: &str, package: &str) -> String {
+    match download_domain.strip_prefix("https://github.com/") {
+        Some(owner) => format!(
+            "https://api.github.com/repos/{}{}/releases?per_page=100",
+            owner, package
+        ),
+        None => format!("{}{}/releases/index.json", download_domain, package),
+    }
+}
+
+pub fn http_client() -> anyhow::Result<Client, anyhow::Error> {
+    Ok(Client::builder()
+        .user_agent(concat!("yacc/", env!("CARGO_PKG_VERSION")))
+        .connect_timeout(CONNECT_TIMEOUT)
+        .build()?)
+}
+
+/// Fetch the releases of every package, either from a single index or per package.
+/// Per package indexes are tried on each mirror in turn.
+pub async fn fetch_releases(
+    client: &Client,
+    mirrors: &[String],
+    packages: &[&str],
+    index: Option<&str>,
+) -> anyhow::Result<HashMap<String, Vec<Release>>, anyhow::Error> {
+    if let Some(index) = index {
+        return client
+            .get(index)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<HashMap<String, Vec<Release>>>()
+            .await
+            .with_context(|| format!("Failed to read release index {}", index));
+    }
+
+    let mut releases = HashMap::new();
+    for package in packages {
+        let mut last_error = anyhow::anyhow!("No mirror configured");
+        for mirror in mirrors {
+            let url = index_url(mirror, package);
+            match fetch_index(client, &url).await {
+                Ok(list) => {
+                    releases.insert(package.to_string(), list);
+                    break;
+                }
+                Err(e) => last_error = e,
+            }
+        }
+        if !releases.contains_key(*package) {
+            return Err(last_error);
+        }
+    }
+    Ok(releases)
+}
+
+async fn fetch_index(client: &Client, url: &str) -> anyhow::Result<Vec<Release>, anyhow::Error> {
+    client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Vec<Release>>()
+        .await
+        .with_context(|| format!("Failed to read release index {}", url))
+}
+
+/// Pick a version for every package and build its download urls, one per mirror.
+pub fn resolve(
+    releases: &HashMap<String, Vec<Release>>,
+    packages: &[&str],
+    mirrors: &[String],
+    arch: &str,
+    selection: &Selection,
+) -> anyhow::Result<Vec<Package>, anyhow::Error> {
+    let cap = selection.version.as_deref().map(Version::parse);
+    let empty = vec![];
+
+    let mut resolved = vec![];
+    for package in packages {
+        if asset_stem(package).is_none() {
+            continue;
+        }
+        let list = releases.get(*package).unwrap_or(&empty);
+
+        let tag = match selection.pins.get(*package) {
+            // Without an index for the package the pin is trusted as is.
+            Some(pin) if list.is_empty() => format!("v{}", pin.trim_start_matches('v')),
+            Some(pin) => list
+                .iter()
+                .map(|r| r.tag_name.clone())
+                .find(|t| t.trim_start_matches('v') == pin.trim_start_matches('v'))
+                .with_context(|| {
+                    let mut available = list
+                        .iter()
+                        .filter(|r| !r.draft)
+                        .map(|r| r.tag_name.trim_start_matches('v'))
+                        .collect::<Vec<_>>();
+                    available.sort_by_key(|v| std::cmp::Reverse(Version::parse(v)));
+                    format!(
+                        "Version {} of {} not found, available: {}",
+                        pin.trim_start_matches('v'),
+                        package,
+                        available.join(", ")
+                    )
+                })?,
+            None => {
+                // Components are versioned apart, only CasaOS itself is capped.
+                let cap = cap.as_ref().filter(|_| *package == "CasaOS");
+                let release = newest(list, package, arch, selection.alpha, cap)
+                    .with_context(|| format!("No release of {} found for {}", package, arch))?;
+                if release.prerelease && !selection.alpha {
+                    print_warn!(
+                        "{} has no stable release for {}, using pre-release {}",
+                        package,
+                        arch,
+                        release.tag_name
+                    );
+                }
+                release.tag_name.clone()
+            }
+        };
+
+        let file_name = asset_name(package, arch, &tag).unwrap();
+        let sha256 = list
+            .iter()
+            .filter(|r| r.tag_name == tag)
+            .flat_map(|r| r.assets.iter())
+            .find(|a| a.name == file_name)
+            .and_then(|a| a.digest.as_deref())
+            .and_then(|d| d.strip_prefix("sha256:"))
+            .map(str::to_string);
+        let mut urls = mirrors.iter().map(|mirror| {
+            format!(
+                "{}{}/releases/download/{}/{}",
+                mirror, package, tag, file_name
+            )
+        });
+        resolved.push(Package {
+            name: package.to_string(),
+            version: tag.trim_start_matches('v').to_string(),
+            url: urls.next().context("No mirror configured")?,
+            fallback_urls: urls.collect(),
+            path: None,
+            sha256,
+            file_name,
+        });
+    }
+    Ok(resolved)
+}
+
+/// Newest release providing a tarball for `arch`.
+/// Stable releases win over pre-releases unless `alpha` is set.
+fn newest<'a>(
+    releases: &'a [Release],
+    package: &str,
+    arch: &str,
+    alpha: bool,
+    cap: Option<&Version>,
+) -> Option<&'a Release> {
+    let candidates = releases
+        .iter()
+        .filter(|r| !r.draft)
+        .filter(|r| {
+            let name = asset_name(package, arch, &r.tag_name).unwrap_or_default();
+            r.assets.iter().any(|a| a.name == name)
+        })
+        .filter(|r| cap.is_none_or(|cap| Version::parse(&r.tag_name).core_cmp(cap).is_le()))
+        .collect::<Vec<_>>();
+
+    let pick = |pre: bool| {
+        candidates
+            .iter()
+            .filter(|r| pre || !r.prerelease)
+            .max_by(|a, b| Version::parse(&a.tag_name).cmp(&Version::parse(&b.tag_name)))
+            .copied()
+    };
+    pick(alpha).or_else(|| pick(true))
+}
+
+/// A CasaOS release version such as `v0.4.3`, `v0.4.3-1` or `v0.4.3-alpha2`.
+///
+/// A numeric suffix is a rebuild and sorts after the plain release,
+/// any other suffix is a pre-release and sorts before it.
+#[derive(Debug)]
+pub struct Version {
+    core: Vec<u64>,
+    suffix: Suffix,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Suffix {
+    Pre(String, u64),
+    Release,
+    Rebuild(u64),
+}
+
+impl Version {
+    pub fn parse(version: &str) -> Version {
+        let version = version.trim().trim_start_matches('v');
+        let (core, suffix) = version.split_once('-').unwrap_or((version, ""));
+        let core = core
+            .split('.')
+            .map(|n| n.parse().unwrap_or(0))
+            .collect::<Vec<u64>>();
+
+        let suffix = if suffix.is_empty() {
+            Suffix::Release
+        } else if let Ok(n) = suffix.parse() {
+            Suffix::Rebuild(n)
+        } else {
+            let split = suffix.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+            let (label, n) = suffix.split_at(split);
+            Suffix::Pre(label.to_string(), n.parse().unwrap_or(0))
+        };
+        Version { core, suffix }
+    }
+
+    /// Compare only the numeric part, ignoring pre-release and rebuild suffixes.
+    pub fn core_cmp(&self, other: &Version) -> Ordering {
+        let len = self.core.len().max(other.core.len());
+        let pad = |v: &Vec<u64>| {
+            let mut v = v.clone();
+            v.resize(len, 0);
+            v
+        };
+        pad(&self.core).cmp(&pad(&other.core))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Version {}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.core_cmp(other)
+            .then_with(|| self.suffix.cmp(&other.suffix))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn release(tag: &str, prerelease: bool, archs: &[&str]) -> Release {
+        Release {
+            tag_name: tag.to_string(),
+            prerelease,
+            draft: false,
+            assets: archs
+                .iter()
+                .map(|arch| Asset {
+                    name: asset_name("CasaOS", arch, tag).unwrap(),
+                    digest: Some(format!("sha256:{}{}", arch, tag)),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_version_order() {
+        assert!(Version::parse("v0.4.3-alpha2") < Version::parse("v0.4.3"));
+        assert!(Version::parse("v0.4.3") < Version::parse("v0.4.3-1"));
+        assert!(Version::parse("v0.4.3-alpha2") < Version::parse("v0.4.3-alpha10"));
+        assert!(Version::parse("v0.4.10") > Version::parse("v0.4.9"));
+        assert_eq!(Version::parse("0.4"), Version::parse("v0.4.0"));
+    }
+
+    #[test]
+    fn test_resolve_newest() {
+        let releases = HashMap::from([(
+            "CasaOS".to_string(),
+            vec![
+                release("v0.4.3-1", false, &["amd64", "arm64"]),
+                release("v0.4.4", false, &["amd64"]),
+                release("v0.4.5-alpha1", true, &["amd64", "arm64"]),
+            ],
+        )]);
+        let domain = &["https://github.com/IceWhaleTech/".to_string()];
+        let selection = Selection::default();
+
+        let amd64 = resolve(&releases, &["CasaOS"], domain, "amd64", &selection).unwrap();
+        assert_eq!(amd64[0].version, "0.4.4");
+        assert_eq!(
+            amd64[0].url,
+            "https://github.com/IceWhaleTech/CasaOS/releases/download/v0.4.4/linux-amd64-casaos-v0.4.4.tar.gz"
+        );
+
+        assert_eq!(amd64[0].sha256.as_deref(), Some("amd64v0.4.4"));
+
+        let arm64 = resolve(&releases, &["CasaOS"], domain, "arm64", &selection).unwrap();
+        assert_eq!(arm64[0].version, "0.4.3-1");
+
+        let alpha = Selection {
+            alpha: true,
+            ..Default::default()
+        };
+        let arm64 = resolve(&releases, &["CasaOS"], domain, "arm64", &alpha).unwrap();
+        assert_eq!(arm64[0].version, "0.4.5-alpha1");
+    }
+
+    #[test]
+    fn test_resolve_overrides() {
+        let releases = HashMap::from([(
+            "CasaOS".to_string(),
+            vec![
+                release("v0.4.3-1", false, &["amd64"]),
+                release("v0.4.4", false, &["amd64"]),
+            ],
+        )]);
+        let domain = &["https://github.com/IceWhaleTech/".to_string()];
+
+        let capped = Selection {
+            version: Some("0.4.3".to_string()),
+            ..Default::default()
+        };
+        let packages = resolve(&releases, &["CasaOS"], domain, "amd64", &capped).unwrap();
+        assert_eq!(packages[0].version, "0.4.3-1");
+        let mut gateway = releases["CasaOS"].clone();
+        for r in gateway.iter_mut() {
+            r.assets[0].name = asset_name("CasaOS-Gateway", "amd64", &r.tag_name).unwrap();
+        }
+        let components = HashMap::from([("CasaOS-Gateway".to_string(), gateway)]);
+        let packages = resolve(&components, &["CasaOS-Gateway"], domain, "amd64", &capped).unwrap();
+        assert_eq!(packages[0].version, "0.4.4");
+
+        let pinned = Selection {
+            pins: HashMap::from([("CasaOS".to_string(), "0.4.2".to_string())]),
+            ..Default::default()
+        };
+        let error = resolve(&releases, &["CasaOS"], domain, "amd64", &pinned).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Version 0.4.2 of CasaOS not found, available: 0.4.4, 0.4.3-1"
+        );
+        let pinned = Selection {
+            pins: HashMap::from([("CasaOS".to_string(), "v0.4.3-1".to_string())]),
+            ..Default::default()
+        };
+        let packages = resolve(&releases, &["CasaOS"], domain, "amd64", &pinned).unwrap();
+        assert_eq!(packages[0].file_name, "linux-amd64-casaos-v0.4.3-1.tar.gz");
+        assert!(packages[0].sha256.is_some());
+
+        // A package missing from the index keeps its pin.
+        let pinned = Selection {
+            pins: HashMap::from([("CasaOS".to_string(), "0.4.2".to_string())]),
+            ..Default::default()
+        };
+        let packages = resolve(&HashMap::new(), &["CasaOS"], domain, "amd64", &pinned).unwrap();
+        assert_eq!(packages[0].file_name, "linux-amd64-casaos-v0.4.2.tar.gz");
+    }
+
+    #[test]
+    fn test_resolve_mirrors() {
+        let releases = HashMap::from([(
+            "CasaOS".to_string(),
+            vec![release("v0.4.4", false, &["amd64"])],
+        )]);
+        let mirrors = [
+            "https://mirror.example.com/IceWhaleTech/".to_string(),
+            "https://github.com/IceWhaleTech/".to_string(),
+        ];
+        let packages = resolve(
+            &releases,
+            &["CasaOS"],
+            &mirrors,
+            "amd64",
+            &Selection::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            packages[0].checksums_url(),
+            "https://mirror.example.com/IceWhaleTech/CasaOS/releases/download/v0.4.4/checksums.txt"
+        );
+        assert_eq!(
+            packages[0].fallback_urls,
+            ["https://github.com/IceWhaleTech/CasaOS/releases/download/v0.4.4/linux-amd64-casaos-v0.4.4.tar.gz"]
+        );
+    }
+}
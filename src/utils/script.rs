@@ -0,0 +1,188 @@
+use crate::print_output;
+use anyhow::Context;
+use console::style;
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    time::{Duration, Instant},
+};
+
+/// Where yacc keeps the output of the scripts it runs, relative to the root.
+pub const LOG_DIR: &str = "var/log/yacc";
+/// How long a package script may run before it is killed.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+/// Variables passed on to scripts, everything else of yacc's environment is dropped.
+const KEPT_ENV: &[&str] = &["PATH", "LANG", "TERM"];
+
+/// Which package scripts may run, by SHA-256 of their content.
+#[derive(Debug, Default, Clone)]
+pub struct Policy {
+    /// When not empty, only these scripts run.
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl Policy {
+    /// Fail on the first script in `scripts` the policy does not let run,
+    /// naming it relative to `base`.
+    pub fn check(&self, base: &Path, scripts: &[PathBuf]) -> anyhow::Result<(), anyhow::Error> {
+        for path in scripts {
+            let sha256 = crate::utils::checksum::sha256_file(path)?;
+            let denied = self.deny.iter().any(|h| h.eq_ignore_ascii_case(&sha256));
+            let allowed =
+                self.allow.is_empty() || self.allow.iter().any(|h| h.eq_ignore_ascii_case(&sha256));
+            if denied || !allowed {
+                anyhow::bail!(
+                    "Script {} with SHA-256 {} is {} by the script policy",
+                    path.strip_prefix(base).unwrap_or(path).display(),
+                    sha256,
+                    if denied { "denied" } else { "not allowed" }
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run `path` with bash, with no stdin, a minimal environment plus `envs`, in its own
+/// directory and process group. Its output is appended to `log`, and it is killed
+/// along with everything it started when `timeout` passes.
+pub fn run(
+    path: &Path,
+    envs: &[(&str, &str)],
+    log: &File,
+    timeout: Duration,
+) -> anyhow::Result<ExitStatus, anyhow::Error> {
+    let mut command = Command::new("bash");
+    command
+        .arg(path)
+        .env_clear()
+        .envs(
+            KEPT_ENV
+                .iter()
+                .filter_map(|k| Some((k, std::env::var(k).ok()?))),
+        )
+        .env("HOME", "/root")
+        .envs(envs.iter().copied())
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log.try_clone()?)
+        .process_group(0);
+    if let Some(dir) = path.parent() {
+        command.current_dir(dir);
+    }
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("Failed to run {}", path.display()))?;
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if Instant::now() >= deadline {
+            // SAFETY: kill takes no pointers, the child leads its own process group
+            // since `process_group(0)` and is not reaped yet, so the group id is its own.
+            unsafe {
+                libc::kill(-(child.id() as i32), libc::SIGKILL);
+            }
+            child.wait()?;
+            anyhow::bail!("{} timed out after {}s", path.display(), timeout.as_secs());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Print a script before it runs, for `--show-scripts`.
+pub fn show(path: &Path) -> anyhow::Result<(), anyhow::Error> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    print_output!(
+        "{}\n{}\n{}",
+        style(format!("--- {} ---", path.display())).dim(),
+        content.trim_end(),
+        style("---").dim()
+    );
+    Ok(())
+}
+
+/// The last `lines` lines of `log` written after `offset`.
+pub fn tail(log: &Path, offset: u64, lines: usize) -> String {
+    let mut bytes = vec![];
+    if let Ok(mut file) = File::open(log) {
+        let _ = file.seek(SeekFrom::Start(offset));
+        let _ = file.read_to_end(&mut bytes);
+    }
+    let output = String::from_utf8_lossy(&bytes);
+    let all: Vec<&str> = output.lines().collect();
+    all[all.len().saturating_sub(lines)..].join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("10-setup.sh");
+        // Set by cargo for every test, so nothing has to change the environment.
+        assert!(std::env::var_os("CARGO_MANIFEST_DIR").is_some());
+        std::fs::write(
+            &script,
+            "echo \"$TO_VERSION $CARGO_MANIFEST_DIR\"; pwd; exit 3\n",
+        )
+        .unwrap();
+        let log_path = dir.path().join("setup.log");
+        let log = File::create(&log_path).unwrap();
+        let status = run(&script, &[("TO_VERSION", "0.4.4")], &log, DEFAULT_TIMEOUT).unwrap();
+        assert_eq!(status.code(), Some(3));
+        let output = tail(&log_path, 0, 5);
+        assert_eq!(
+            output,
+            format!("0.4.4 \n{}", dir.path().canonicalize().unwrap().display())
+        );
+        assert_eq!(
+            tail(&log_path, 0, 1),
+            dir.path().canonicalize().unwrap().display().to_string()
+        );
+
+        std::fs::write(&script, "printf 'caf\\351\\nfailed\\n'; exit 1\n").unwrap();
+        let offset = std::fs::metadata(&log_path).unwrap().len();
+        run(&script, &[], &log, DEFAULT_TIMEOUT).unwrap();
+        assert_eq!(tail(&log_path, offset, 2), "caf\u{fffd}\nfailed");
+
+        std::fs::write(&script, "sleep 10\n").unwrap();
+        let error = run(&script, &[], &log, Duration::from_millis(200)).unwrap_err();
+        assert!(error.to_string().contains("timed out"));
+    }
+
+    #[test]
+    fn test_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("10-setup.sh");
+        std::fs::write(&script, "true\n").unwrap();
+        let sha256 = crate::utils::checksum::sha256_file(&script).unwrap();
+        let scripts = vec![script];
+
+        assert!(Policy::default().check(dir.path(), &scripts).is_ok());
+        let deny = Policy {
+            deny: vec![sha256.to_uppercase()],
+            ..Default::default()
+        };
+        assert!(deny.check(dir.path(), &scripts).is_err());
+        let allow = Policy {
+            allow: vec!["0".repeat(64)],
+            ..Default::default()
+        };
+        assert!(allow.check(dir.path(), &scripts).is_err());
+        let allow = Policy {
+            allow: vec![sha256],
+            ..Default::default()
+        };
+        assert!(allow.check(dir.path(), &scripts).is_ok());
+    }
+}
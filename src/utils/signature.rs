@@ -0,0 +1,127 @@
+use crate::utils::release::Package;
+use anyhow::Context;
+use minisign_verify::{PublicKey, Signature};
+use reqwest::Client;
+use std::path::Path;
+
+/// Load a minisign public key, given either inline as base64 or as a path to a `.pub` file.
+pub fn load_key(key: &str) -> anyhow::Result<PublicKey, anyhow::Error> {
+    let path = Path::new(key);
+    if path.is_file() {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read trusted key {}", path.display()))?;
+        return PublicKey::decode(&content)
+            .or_else(|_| PublicKey::from_base64(content.trim()))
+            .map_err(|e| anyhow::anyhow!("Invalid trusted key {}: {}", path.display(), e));
+    }
+    PublicKey::from_base64(key.trim()).map_err(|e| anyhow::anyhow!("Invalid trusted key: {}", e))
+}
+
+/// The keys shipped with yacc followed by the `extra` ones given by the user.
+pub fn trusted_keys(
+    shipped: &[&str],
+    extra: &[String],
+) -> anyhow::Result<Vec<PublicKey>, anyhow::Error> {
+    shipped
+        .iter()
+        .map(|k| k.to_string())
+        .chain(extra.iter().cloned())
+        .map(|k| load_key(&k))
+        .collect()
+}
+
+/// Fetch the detached `.minisig` signature published next to a package.
+pub async fn fetch_signature(
+    client: &Client,
+    package: &Package,
+) -> anyhow::Result<Option<String>, anyhow::Error> {
+    let response = client.get(package.signature_url()).send().await?;
+    if !response.status().is_success() {
+        return Ok(None);
+    }
+    Ok(Some(response.text().await?))
+}
+
+/// Verify a downloaded package against a detached signature made by any of the trusted keys.
+pub fn verify(
+    path: &Path,
+    package: &Package,
+    signature: &str,
+    keys: &[PublicKey],
+) -> anyhow::Result<(), anyhow::Error> {
+    let signature = Signature::decode(signature)
+        .map_err(|e| anyhow::anyhow!("Invalid signature for {}: {}", package.name, e))?;
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    if keys
+        .iter()
+        .any(|key| key.verify(&data, &signature, false).is_ok())
+    {
+        Ok(())
+    } else {
+        anyhow::bail!(
+            "Signature of {} ({}) was not made by a trusted key",
+            package.name,
+            package.file_name
+        )
+    }
+}
+
+/// A package signed by a throwaway key, for tests.
+#[cfg(test)]
+pub mod fixture {
+    pub const TARBALL: &[u8] = b"not really a tarball";
+    pub const PUBLIC_KEY: &str = "untrusted comment: minisign public key: 81A23C9260FC07F2
+RWTyB/xgkjyigec7Wz5JxH6YklRmWzxsSpVGJx8XlSfNRcs72qpJ+DRi
+";
+    pub const SIGNATURE: &str = "untrusted comment: signature from rsign secret key
+RUTyB/xgkjyige8Cyja/iRBZ37CHkMm/VCFuX/7fCZYrIBFE+C4iTXBskDhZAn1QnXOAAttvKwIi627Ka2Jnn92BSgGmfOUK+AA=
+trusted comment: linux-amd64-casaos-v0.4.4.tar.gz
+6kxriW0P3QB+NjIMp5DzYHE5TU7xV4VjcZO1a26N65H4QmfowqnUKfKC/zfAi5r1G9kKOspufDpuPu3wl0N9AQ==
+";
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use fixture::{PUBLIC_KEY, SIGNATURE, TARBALL};
+
+    const OTHER_KEY: &str = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+
+    fn package() -> Package {
+        Package::test("http://localhost")
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let dir = tempfile::tempdir().unwrap();
+        let key_file = dir.path().join("release.pub");
+        std::fs::write(&key_file, PUBLIC_KEY).unwrap();
+        let file = dir.path().join("casaos.tar.gz");
+        std::fs::write(&file, TARBALL).unwrap();
+
+        let trusted = load_key(key_file.to_str().unwrap()).unwrap();
+        let other = load_key(OTHER_KEY).unwrap();
+
+        assert!(verify(&file, &package(), SIGNATURE, &[other.clone(), trusted]).is_ok());
+        assert!(verify(&file, &package(), SIGNATURE, &[other]).is_err());
+
+        std::fs::write(&file, b"tampered").unwrap();
+        let trusted = load_key(key_file.to_str().unwrap()).unwrap();
+        assert!(verify(&file, &package(), SIGNATURE, &[trusted]).is_err());
+    }
+
+    #[test]
+    fn test_shipped_keys() {
+        for key in crate::consts::TRUSTED_KEYS {
+            assert!(load_key(key).is_ok(), "shipped key {} does not decode", key);
+        }
+    }
+
+    #[test]
+    #[ignore = "no CasaOS release key is shipped yet"]
+    fn test_shipped_keys_present() {
+        assert!(!crate::consts::TRUSTED_KEYS.is_empty());
+    }
+}
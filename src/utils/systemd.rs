@@ -1,53 +1,29 @@
+use crate::utils::oplog;
 use std::{
-    io::{Error, ErrorKind, Read},
-    process::ExitStatus,
+    io::{Error, ErrorKind},
+    path::Path,
+    process::{Command, ExitStatus},
 };
 
+/// Invokes `systemctl $args` quietly, its output goes to the operation log.
 pub fn systemctl(args: Vec<&str>) -> std::io::Result<ExitStatus> {
-    let mut child = std::process::Command::new("systemctl")
-        .args(args)
-        .stdout(std::process::Stdio::piped())
-        .stderr(std::process::Stdio::null())
-        .spawn()?;
-    child.wait()
+    Ok(oplog::capture(Command::new("systemctl").args(args))?.status)
 }
 
 /// Invokes `systemctl $args` and captures stdout stream
 fn systemctl_capture(args: Vec<&str>) -> std::io::Result<String> {
-    let mut child = std::process::Command::new("systemctl")
-        .args(args.clone())
-        .stdout(std::process::Stdio::piped())
-        .stderr(std::process::Stdio::null())
-        .spawn()?;
-    let exitcode = child.wait()?;
-
-    match exitcode.success() {
-        true => {
-            let mut stdout: Vec<u8> = Vec::new();
-            if let Ok(size) = child.stdout.unwrap().read_to_end(&mut stdout) {
-                if size > 0 {
-                    if let Ok(s) = String::from_utf8(stdout) {
-                        Ok(s)
-                    } else {
-                        Err(Error::new(
-                            ErrorKind::InvalidData,
-                            "Invalid utf8 data in stdout",
-                        ))
-                    }
-                } else {
-                    Err(Error::new(ErrorKind::InvalidData, "systemctl stdout empty"))
-                }
-            } else {
-                Err(Error::new(ErrorKind::InvalidData, "systemctl stdout empty"))
-            }
-        }
-        false => Err(Error::new(
-            ErrorKind::Other,
-            format!("systemctl {:?} failed", args),
-        )),
+    let output = oplog::capture(Command::new("systemctl").args(args.clone()))?;
+    if !output.status.success() {
+        return Err(Error::other(format!("systemctl {:?} failed", args)));
+    }
+    if output.stdout.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, "systemctl stdout empty"));
     }
+    String::from_utf8(output.stdout)
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "Invalid utf8 data in stdout"))
 }
 
+#[allow(dead_code)]
 pub fn status(unit: &str) -> std::io::Result<String> {
     systemctl_capture(vec!["status", unit])
 }
@@ -57,22 +33,50 @@ pub fn is_active(unit: &str) -> std::io::Result<bool> {
     Ok(status.trim_end().eq("active"))
 }
 
+/// Whether a unit file is installed, whether or not the unit is running.
 pub fn exists(unit: &str) -> std::io::Result<bool> {
-    let status = status(unit);
-    Ok(status.is_ok())
+    // `status` fails for stopped units, `cat` only for missing ones.
+    let cat = systemctl(vec!["cat", unit])?;
+    Ok(cat.success())
+}
+
+pub fn is_enabled(unit: &str) -> std::io::Result<bool> {
+    let status = systemctl(vec!["is-enabled", "--quiet", unit])?;
+    Ok(status.success())
 }
 
 pub fn enable(unit: &str) -> std::io::Result<bool> {
-    let enable = systemctl(vec!["enable", "--now", unit]);
-    Ok(enable.is_ok())
+    let enable = systemctl(vec!["enable", "--now", unit])?;
+    Ok(enable.success())
 }
 
 pub fn disable(unit: &str) -> std::io::Result<bool> {
-    let disable = systemctl(vec!["disable", "--now", unit]);
-    Ok(disable.is_ok())
+    let disable = systemctl(vec!["disable", "--now", unit])?;
+    Ok(disable.success())
+}
+
+/// Make systemd pick up new or changed unit files.
+pub fn daemon_reload() -> std::io::Result<bool> {
+    let reload = systemctl(vec!["daemon-reload"])?;
+    Ok(reload.success())
+}
+
+/// Enable a unit of the system image at `root`, without a running systemd.
+pub fn enable_in(root: &Path, unit: &str) -> std::io::Result<bool> {
+    let root = root.to_string_lossy();
+    let enable = systemctl(vec!["--root", &root, "enable", unit])?;
+    Ok(enable.success())
+}
+
+/// Disable a unit of the system image at `root`, without a running systemd.
+pub fn disable_in(root: &Path, unit: &str) -> std::io::Result<bool> {
+    let root = root.to_string_lossy();
+    let disable = systemctl(vec!["--root", &root, "disable", unit])?;
+    Ok(disable.success())
 }
 
 #[test]
+#[ignore = "needs the CasaOS systemd units installed on this machine"]
 fn test_check_exists() {
     assert!(exists("casaos-gateway.service").unwrap());
     assert!(exists("casaos-message-bus.service").unwrap());
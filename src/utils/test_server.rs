@@ -0,0 +1,165 @@
+//! A tiny HTTP/1.1 server standing in for GitHub and the mirrors in tests.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// Misbehaviour injected into the next request for a path.
+#[derive(Debug, Clone, Copy)]
+pub enum Fault {
+    /// Answer with this status and no body.
+    Status(u16),
+    /// Announce the full body but close the connection halfway through it.
+    Cut,
+}
+
+#[derive(Default)]
+struct State {
+    hits: HashMap<String, usize>,
+    faults: Vec<(String, Fault)>,
+}
+
+pub struct TestServer {
+    addr: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl TestServer {
+    /// Serve the given files, answering `404` for every other path.
+    pub fn start(files: Vec<(&str, Vec<u8>)>) -> TestServer {
+        TestServer::start_with_faults(files, vec![])
+    }
+
+    /// Like `start`, but the first requests to a path fail with the given faults, in order.
+    pub fn start_with_faults(
+        files: Vec<(&str, Vec<u8>)>,
+        faults: Vec<(&str, Fault)>,
+    ) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let files: HashMap<String, Vec<u8>> = files
+            .into_iter()
+            .map(|(path, body)| (path.to_string(), body))
+            .collect();
+        let files = Arc::new(files);
+        let state = Arc::new(Mutex::new(State {
+            hits: HashMap::new(),
+            faults: faults
+                .into_iter()
+                .map(|(path, fault)| (path.to_string(), fault))
+                .collect(),
+        }));
+
+        let server_state = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let files = files.clone();
+                let state = server_state.clone();
+                thread::spawn(move || {
+                    let _ = handle(stream, &files, &state);
+                });
+            }
+        });
+        TestServer { addr, state }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// Number of requests received for a path.
+    pub fn hits(&self, path: &str) -> usize {
+        let state = self.state.lock().unwrap();
+        state.hits.get(path).copied().unwrap_or_default()
+    }
+}
+
+fn handle(
+    stream: TcpStream,
+    files: &HashMap<String, Vec<u8>>,
+    state: &Mutex<State>,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            headers.insert(key.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let fault = {
+        let mut state = state.lock().unwrap();
+        *state.hits.entry(path.clone()).or_default() += 1;
+        let next = state.faults.iter().position(|(p, _)| *p == path);
+        next.map(|i| state.faults.remove(i).1)
+    };
+
+    let mut stream = stream;
+    if let Some(Fault::Status(status)) = fault {
+        return write!(
+            stream,
+            "HTTP/1.1 {} Injected\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            status
+        );
+    }
+    let Some(body) = files.get(&path) else {
+        return write!(
+            stream,
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+    };
+
+    let start = headers
+        .get("range")
+        .and_then(|r| r.strip_prefix("bytes="))
+        .and_then(|r| r.strip_suffix('-'))
+        .and_then(|r| r.parse::<usize>().ok());
+    let (status, body, range) = match start {
+        Some(start) if start >= body.len() => (
+            "416 Range Not Satisfiable",
+            &body[..0],
+            format!("Content-Range: bytes */{}\r\n", body.len()),
+        ),
+        Some(start) => (
+            "206 Partial Content",
+            &body[start..],
+            format!(
+                "Content-Range: bytes {}-{}/{}\r\n",
+                start,
+                body.len() - 1,
+                body.len()
+            ),
+        ),
+        _ => ("200 OK", &body[..], String::new()),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n",
+        status,
+        body.len(),
+        range
+    )?;
+    if method == "HEAD" {
+        return stream.flush();
+    }
+    match fault {
+        Some(Fault::Cut) => stream.write_all(&body[..body.len() / 2])?,
+        _ => stream.write_all(body)?,
+    }
+    stream.flush()
+}
@@ -0,0 +1,238 @@
+use crate::{print_ok, print_output, print_warn, utils::systemd};
+use anyhow::Context;
+use console::style;
+use std::path::{Path, PathBuf};
+
+/// State of a path before the install touched it.
+#[derive(Debug)]
+enum Snapshot {
+    /// The file did not exist and is removed again.
+    Created,
+    /// The file was copied into the backup directory.
+    File(PathBuf),
+    /// The path was a symlink to this target.
+    Link(PathBuf),
+}
+
+/// Records every change an install makes so it can be undone if a later step fails.
+pub struct Transaction {
+    backup_dir: PathBuf,
+    /// Alternate root whose services are enabled offline, `None` for the running system.
+    root: Option<PathBuf>,
+    files: Vec<(PathBuf, Snapshot)>,
+    dirs: Vec<PathBuf>,
+    /// Services that were enabled before the install disabled them.
+    disabled: Vec<String>,
+    /// Services the install enabled that were not enabled before.
+    enabled: Vec<String>,
+}
+
+/// What a rollback restored and what it could not.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub restored: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub reenabled: Vec<String>,
+    pub disabled: Vec<String>,
+    pub failures: Vec<String>,
+}
+
+impl Transaction {
+    /// Start a transaction keeping the snapshots of overwritten files in `backup_dir`.
+    pub fn new(
+        backup_dir: &Path,
+        root: Option<&Path>,
+    ) -> anyhow::Result<Transaction, anyhow::Error> {
+        std::fs::create_dir_all(backup_dir)
+            .with_context(|| format!("Failed to create {}", backup_dir.display()))?;
+        Ok(Transaction {
+            backup_dir: backup_dir.to_path_buf(),
+            root: root.map(Path::to_path_buf),
+            files: vec![],
+            dirs: vec![],
+            disabled: vec![],
+            enabled: vec![],
+        })
+    }
+
+    /// Snapshot `path` before it is written, once per transaction.
+    pub fn snapshot(&mut self, path: &Path) -> anyhow::Result<(), anyhow::Error> {
+        if self.files.iter().any(|(p, _)| p == path) {
+            return Ok(());
+        }
+        let snapshot = match std::fs::symlink_metadata(path) {
+            Err(_) => Snapshot::Created,
+            Ok(meta) if meta.file_type().is_symlink() => Snapshot::Link(std::fs::read_link(path)?),
+            Ok(_) => {
+                let backup = self.backup_dir.join(self.files.len().to_string());
+                std::fs::copy(path, &backup)
+                    .with_context(|| format!("Failed to back up {}", path.display()))?;
+                Snapshot::File(backup)
+            }
+        };
+        self.files.push((path.to_path_buf(), snapshot));
+        Ok(())
+    }
+
+    /// Create `dir` and its missing parents, remembering which ones were new.
+    pub fn create_dir_all(&mut self, dir: &Path) -> anyhow::Result<(), anyhow::Error> {
+        let missing = dir
+            .ancestors()
+            .take_while(|d| !d.exists())
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        self.dirs.extend(missing.into_iter().rev());
+        Ok(())
+    }
+
+    /// Install `src` at `dest`, keeping its permissions. Symlinks are copied as links.
+    pub fn install_file(&mut self, src: &Path, dest: &Path) -> anyhow::Result<(), anyhow::Error> {
+        if let Some(parent) = dest.parent() {
+            self.create_dir_all(parent)?;
+        }
+        self.snapshot(dest)?;
+
+        // Never write through an existing link, and make room for a new one.
+        let src_is_link = std::fs::symlink_metadata(src)?.file_type().is_symlink();
+        match std::fs::symlink_metadata(dest) {
+            Ok(m) if src_is_link || m.file_type().is_symlink() => std::fs::remove_file(dest)?,
+            _ => {}
+        }
+        if src_is_link {
+            std::os::unix::fs::symlink(std::fs::read_link(src)?, dest)
+        } else {
+            std::fs::copy(src, dest).map(|_| ())
+        }
+        .with_context(|| format!("Failed to install {}", dest.display()))
+    }
+
+    /// Remember that an enabled service was disabled.
+    pub fn service_disabled(&mut self, service: &str) {
+        self.disabled.push(service.to_string());
+    }
+
+    /// Remember that a service was enabled which was not enabled before.
+    pub fn service_enabled(&mut self, service: &str) {
+        if !self.disabled.iter().any(|s| s == service) {
+            self.enabled.push(service.to_string());
+        }
+    }
+
+    /// Undo every recorded change, newest first, carrying on past failures.
+    pub fn rollback(self) -> Report {
+        let mut report = Report::default();
+
+        for (path, snapshot) in self.files.into_iter().rev() {
+            let result = match &snapshot {
+                Snapshot::Created => std::fs::remove_file(&path),
+                Snapshot::File(backup) => {
+                    let _ = std::fs::remove_file(&path);
+                    std::fs::copy(backup, &path).map(|_| ())
+                }
+                Snapshot::Link(target) => {
+                    let _ = std::fs::remove_file(&path);
+                    std::os::unix::fs::symlink(target, &path)
+                }
+            };
+            match (result, snapshot) {
+                (Ok(_), Snapshot::Created) => report.removed.push(path),
+                (Ok(_), _) => report.restored.push(path),
+                (Err(e), _) if e.kind() == std::io::ErrorKind::NotFound => {}
+                (Err(e), _) => {
+                    report
+                        .failures
+                        .push(format!("Failed to restore {}: {}", path.display(), e))
+                }
+            }
+        }
+        for dir in self.dirs.into_iter().rev() {
+            // Only empty directories are removed, anything else was not ours.
+            let _ = std::fs::remove_dir(dir);
+        }
+
+        for service in self.enabled {
+            let disabled = match &self.root {
+                Some(root) => systemd::disable_in(root, &service),
+                None => systemd::disable(&service),
+            };
+            match disabled {
+                Ok(true) => report.disabled.push(service),
+                _ => report
+                    .failures
+                    .push(format!("Failed to disable {}", service)),
+            }
+        }
+        for service in self.disabled {
+            let enabled = match &self.root {
+                Some(root) => systemd::enable_in(root, &service),
+                None => systemd::enable(&service),
+            };
+            match enabled {
+                Ok(true) => report.reenabled.push(service),
+                _ => report
+                    .failures
+                    .push(format!("Failed to re-enable {}", service)),
+            }
+        }
+        report
+    }
+}
+
+impl Report {
+    pub fn print(&self) {
+        print_output!("{}", style("Rollback report:").bold());
+        print_output!("  {} files restored", self.restored.len());
+        print_output!("  {} new files removed", self.removed.len());
+        if !self.reenabled.is_empty() {
+            print_ok!("Re-enabled {}", self.reenabled.join(", "));
+        }
+        if !self.disabled.is_empty() {
+            print_ok!("Disabled {}", self.disabled.join(", "));
+        }
+        for failure in self.failures.iter() {
+            print_warn!("{}", failure);
+        }
+        print_warn!("Changes made by migration and setup scripts can not be rolled back.");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rollback_restores_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        let src = dir.path().join("src");
+        std::fs::create_dir_all(root.join("etc")).unwrap();
+        std::fs::create_dir_all(&src).unwrap();
+        std::fs::write(root.join("etc/existing.conf"), "old").unwrap();
+        std::fs::write(src.join("file"), "new").unwrap();
+
+        let mut tx = Transaction::new(&dir.path().join("backup"), None).unwrap();
+        tx.install_file(&src.join("file"), &root.join("etc/existing.conf"))
+            .unwrap();
+        tx.install_file(&src.join("file"), &root.join("usr/bin/casaos"))
+            .unwrap();
+        // A second write to the same path keeps the first snapshot.
+        tx.install_file(&src.join("file"), &root.join("etc/existing.conf"))
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.join("etc/existing.conf")).unwrap(),
+            "new"
+        );
+
+        let report = tx.rollback();
+        assert_eq!(
+            std::fs::read_to_string(root.join("etc/existing.conf")).unwrap(),
+            "old"
+        );
+        assert!(!root.join("usr").exists());
+        assert_eq!(report.restored, [root.join("etc/existing.conf")]);
+        assert_eq!(report.removed, [root.join("usr/bin/casaos")]);
+        assert!(report.failures.is_empty());
+    }
+}